@@ -0,0 +1,20 @@
+#!/bin/sh
+# Vendors the pinned mermaid runtime and its license into this directory.
+#
+# The first run records the checksums in SHA256SUMS, which is committed with
+# the files; later runs (e.g. on another machine) verify against it.
+set -eu
+
+VERSION=10.9.1
+BASE="https://cdn.jsdelivr.net/npm/mermaid@${VERSION}"
+
+cd "$(dirname "$0")"
+curl -fsSL -o mermaid.min.js "${BASE}/dist/mermaid.min.js"
+curl -fsSL -o LICENSE "${BASE}/LICENSE"
+
+if [ -f SHA256SUMS ]; then
+    sha256sum -c SHA256SUMS
+else
+    sha256sum mermaid.min.js LICENSE > SHA256SUMS
+    echo "Recorded checksums of mermaid ${VERSION} in SHA256SUMS"
+fi
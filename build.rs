@@ -0,0 +1,20 @@
+use std::path::Path;
+
+/// The mermaid runtime embedded into the binary, vendored by assets/mermaid/fetch.sh
+const MERMAID_JS: &str = "assets/mermaid/mermaid.min.js";
+
+fn main() {
+    println!("cargo:rustc-check-cfg=cfg(dogmv_mermaid)");
+    println!("cargo:rerun-if-changed=assets/mermaid");
+
+    if Path::new(MERMAID_JS).is_file() {
+        println!("cargo:rustc-cfg=dogmv_mermaid");
+    } else {
+        // Diagrams show their source with a note instead
+        println!(
+            "cargo:warning={} not found, building without the mermaid runtime \
+             (run assets/mermaid/fetch.sh to vendor it)",
+            MERMAID_JS
+        );
+    }
+}
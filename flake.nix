@@ -33,8 +33,15 @@
           wrapGAppsHook4
         ];
 
-        # Source filtering
-        src = craneLib.cleanCargoSource ./.;
+        # Source filtering (keep the stylesheets, scripts and assets that are
+        # embedded into the binary)
+        src = pkgs.lib.cleanSourceWith {
+          src = ./.;
+          filter = path: type:
+            (craneLib.filterCargoSources path type)
+            || (builtins.match ".*\\.(css|js)$" path != null)
+            || (pkgs.lib.hasInfix "/assets/" path);
+        };
 
         # Common arguments for crane
         commonArgs = {
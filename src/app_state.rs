@@ -0,0 +1,453 @@
+use crate::file_system::{FileWatcher, WatchEvent};
+use crate::markdown::{Theme, ThemeChoice};
+use crate::models::{History, HistoryEntry};
+use crate::ui::{
+    display_markdown, patch_markdown, query_scroll_position, rerender_markdown, Preview,
+    ReloadMode, ScrollTarget,
+};
+use async_channel::Sender;
+use gtk4::gio;
+use log::{error, info};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use std::sync::{Arc, Mutex};
+
+// プレビュータブ1つ分の状態を保持する構造体
+#[derive(Clone)]
+pub struct AppState {
+    inner: Rc<TabState>,
+}
+
+/// The fields of an `AppState`, shared by all its clones
+pub struct TabState {
+    pub current_file: Arc<Mutex<Option<PathBuf>>>,
+    pub root_dir: Arc<Mutex<Option<PathBuf>>>,
+    pub preview: Preview,
+    pub watcher: Arc<Mutex<FileWatcher>>,
+    /// Last known scroll offset of each file shown in this session
+    pub scroll_positions: Arc<Mutex<HashMap<PathBuf, f64>>>,
+    /// Where to scroll once the next page has finished loading
+    pub pending_scroll: Arc<Mutex<Option<ScrollTarget>>>,
+    /// How the watcher refreshes the current file after it changes
+    pub reload_mode: ReloadMode,
+    /// Theme picked in the header bar; `None` follows the desktop color scheme
+    pub chosen_theme: Arc<Mutex<Option<ThemeChoice>>>,
+    /// Keeps the desktop color scheme notifications alive
+    pub color_scheme_subscription: Arc<Mutex<Option<gio::SignalSubscription>>>,
+    /// Documents visited, for going back and forward
+    pub history: Arc<Mutex<History>>,
+    /// Called with the path of every file opened
+    file_opened_listeners: RefCell<Vec<Box<dyn Fn(&Path)>>>,
+    /// Called whenever the history changes
+    history_listeners: RefCell<Vec<Box<dyn Fn(&History)>>>,
+    /// Called with the path of the current file each time it is re-read
+    reloaded_listeners: RefCell<Vec<Box<dyn Fn(&Path)>>>,
+}
+
+impl Deref for AppState {
+    type Target = TabState;
+
+    fn deref(&self) -> &TabState {
+        &self.inner
+    }
+}
+
+/// A handle on a tab's state that doesn't keep the tab alive.
+///
+/// Closures stored in the tab's WebView or in the state's own listeners must
+/// hold one of these instead of an `AppState`, or the tab is never freed.
+#[derive(Clone)]
+pub struct WeakAppState {
+    inner: Weak<TabState>,
+}
+
+impl WeakAppState {
+    /// Returns the state, or `None` once the tab has been closed and dropped.
+    pub fn upgrade(&self) -> Option<AppState> {
+        self.inner.upgrade().map(|inner| AppState { inner })
+    }
+}
+
+impl AppState {
+    pub fn new(
+        preview: &Preview,
+        root_dir: &Path,
+        watch_events: Sender<WatchEvent>,
+        reload_mode: ReloadMode,
+    ) -> Self {
+        let inner = TabState {
+            current_file: Arc::new(Mutex::new(None)),
+            root_dir: Arc::new(Mutex::new(Some(root_dir.to_path_buf()))),
+            preview: preview.clone(),
+            watcher: Arc::new(Mutex::new(FileWatcher::new(watch_events))),
+            scroll_positions: Arc::new(Mutex::new(HashMap::new())),
+            pending_scroll: Arc::new(Mutex::new(None)),
+            reload_mode,
+            chosen_theme: Arc::new(Mutex::new(None)),
+            color_scheme_subscription: Arc::new(Mutex::new(None)),
+            history: Arc::new(Mutex::new(History::new())),
+            file_opened_listeners: RefCell::new(Vec::new()),
+            history_listeners: RefCell::new(Vec::new()),
+            reloaded_listeners: RefCell::new(Vec::new()),
+        };
+        Self {
+            inner: Rc::new(inner),
+        }
+    }
+
+    /// Creates the state of another tab showing `preview`.
+    ///
+    /// The new tab starts without a file, scroll positions or history of its
+    /// own. The root directory and the theme picked are shared with this one.
+    pub fn new_tab(&self, preview: &Preview, watch_events: Sender<WatchEvent>) -> Self {
+        let inner = TabState {
+            current_file: Arc::new(Mutex::new(None)),
+            root_dir: Arc::clone(&self.root_dir),
+            preview: preview.clone(),
+            watcher: Arc::new(Mutex::new(FileWatcher::new(watch_events))),
+            scroll_positions: Arc::new(Mutex::new(HashMap::new())),
+            pending_scroll: Arc::new(Mutex::new(None)),
+            reload_mode: self.reload_mode,
+            chosen_theme: Arc::clone(&self.chosen_theme),
+            color_scheme_subscription: Arc::clone(&self.color_scheme_subscription),
+            history: Arc::new(Mutex::new(History::new())),
+            file_opened_listeners: RefCell::new(Vec::new()),
+            history_listeners: RefCell::new(Vec::new()),
+            reloaded_listeners: RefCell::new(Vec::new()),
+        };
+        Self {
+            inner: Rc::new(inner),
+        }
+    }
+
+    /// Returns a handle on this state that doesn't keep the tab alive.
+    pub fn downgrade(&self) -> WeakAppState {
+        WeakAppState {
+            inner: Rc::downgrade(&self.inner),
+        }
+    }
+
+    /// Returns `true` if both states belong to the same tab.
+    pub fn same_tab(&self, other: &AppState) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// Stops watching the current file, e.g. when the tab is closed.
+    pub fn close(&self) {
+        if let Ok(mut watcher) = self.watcher.lock() {
+            watcher.close();
+        }
+    }
+
+    /// Calls `f` with the path of each file opened from now on, however it was opened.
+    pub fn connect_file_opened<F: Fn(&Path) + 'static>(&self, f: F) {
+        self.file_opened_listeners.borrow_mut().push(Box::new(f));
+    }
+
+    /// Calls `f` with the history whenever it changes (e.g. to update the back button).
+    pub fn connect_history_changed<F: Fn(&History) + 'static>(&self, f: F) {
+        self.history_listeners.borrow_mut().push(Box::new(f));
+    }
+
+    /// Calls `f` with the path of the current file each time it is re-read,
+    /// e.g. after it changed on disk.
+    pub fn connect_reloaded<F: Fn(&Path) + 'static>(&self, f: F) {
+        self.reloaded_listeners.borrow_mut().push(Box::new(f));
+    }
+
+    /// Makes `path` the current file, moves the watcher to it and displays it.
+    ///
+    /// The scroll offset of the file being left is remembered, and the file
+    /// being opened returns to where it was last scrolled to.
+    pub fn open_file(&self, path: &Path) {
+        self.navigate(path, None, None);
+    }
+
+    /// Opens `new` as a rendered diff against `old` (see `render_diff`).
+    ///
+    /// The comparison lasts until another file is opened; changes to `new` on
+    /// disk are shown as usual. `old` is not watched: it is read again
+    /// whenever `new` is reloaded (after a change or with Ctrl+R).
+    pub fn open_diff(&self, old: &Path, new: &Path) {
+        info!("Comparing {} with {}", new.display(), old.display());
+        self.navigate(new, None, Some(old.to_path_buf()));
+    }
+
+    /// Opens `path` from a link in a document.
+    ///
+    /// The page is scrolled to `fragment` if given, or starts at the top.
+    pub fn open_link(&self, path: &Path, fragment: Option<String>) {
+        let target = match fragment {
+            Some(fragment) => ScrollTarget::Fragment(fragment),
+            None => ScrollTarget::Offset(0.0),
+        };
+        self.navigate(path, Some(target), None);
+    }
+
+    /// Scrolls the document shown to the anchor `fragment`, recording it in the history.
+    pub fn open_fragment(&self, fragment: String) {
+        let Some(path) = self.current_file.lock().ok().and_then(|f| f.clone()) else {
+            return;
+        };
+        let state = self.clone();
+        query_scroll_position(&self.preview.webview, move |offset| {
+            state.record_history(HistoryEntry::new(path, Some(fragment.clone())), offset);
+            state.preview.scroll_to(&ScrollTarget::Fragment(fragment));
+        });
+    }
+
+    /// Goes back to the previous document (or anchor) in the history.
+    pub fn go_back(&self) {
+        self.step_history(History::back);
+    }
+
+    /// Goes forward to the next document (or anchor) in the history.
+    pub fn go_forward(&self) {
+        self.step_history(History::forward);
+    }
+
+    /// Opens `path` and scrolls to `target`, or where the file was last scrolled to.
+    ///
+    /// The document is shown compared with `compare_with` if given.
+    fn navigate(&self, path: &Path, target: Option<ScrollTarget>, compare_with: Option<PathBuf>) {
+        info!("Opening file: {}", path.display());
+
+        self.preview.set_diff_base(compare_with);
+        let previous = self.switch_to(path);
+        let fragment = match target {
+            Some(ScrollTarget::Fragment(ref fragment)) => Some(fragment.clone()),
+            _ => None,
+        };
+
+        let state = self.clone();
+        let path = path.to_path_buf();
+        query_scroll_position(&self.preview.webview, move |offset| {
+            if let (Some(previous), Some(y)) = (previous, offset) {
+                state.remember_scroll(previous, y);
+            }
+            state.record_history(HistoryEntry::new(path.clone(), fragment), offset);
+            let restore = target.or_else(|| state.remembered_scroll(&path));
+            state.show(&path, restore);
+        });
+    }
+
+    /// Moves through the history with `step` and shows the entry it lands on.
+    ///
+    /// Entries return to where they were scrolled to when they were left.
+    fn step_history(&self, step: fn(&mut History) -> Option<HistoryEntry>) {
+        let state = self.clone();
+        query_scroll_position(&self.preview.webview, move |offset| {
+            let entry = state.history.lock().ok().and_then(|mut history| {
+                if let Some(y) = offset {
+                    history.set_scroll(y);
+                }
+                step(&mut history)
+            });
+            let Some(entry) = entry else {
+                return;
+            };
+            state.notify_history();
+
+            let target = entry
+                .scroll
+                .map(ScrollTarget::Offset)
+                .or_else(|| entry.fragment.map(ScrollTarget::Fragment));
+            if state.is_current_file(&entry.path) {
+                // Another anchor in the same document
+                if let Some(target) = target {
+                    state.preview.scroll_to(&target);
+                }
+                return;
+            }
+
+            info!("Going to {} in the history", entry.path.display());
+            state.preview.set_diff_base(None);
+            if let (Some(previous), Some(y)) = (state.switch_to(&entry.path), offset) {
+                state.remember_scroll(previous, y);
+            }
+            let restore = target.or_else(|| state.remembered_scroll(&entry.path));
+            state.show(&entry.path, restore);
+        });
+    }
+
+    /// Makes `path` the current file and moves the watcher to it.
+    ///
+    /// Returns the file that was current before.
+    fn switch_to(&self, path: &Path) -> Option<PathBuf> {
+        let previous = self
+            .current_file
+            .lock()
+            .ok()
+            .and_then(|mut current_file| current_file.replace(path.to_path_buf()));
+
+        if let Ok(mut watcher) = self.watcher.lock() {
+            watcher.watch(path);
+        }
+
+        for listener in self.file_opened_listeners.borrow().iter() {
+            listener(path);
+        }
+        previous
+    }
+
+    /// Adds `entry` to the history, after recording `offset` for the entry being left.
+    fn record_history(&self, entry: HistoryEntry, offset: Option<f64>) {
+        if let Ok(mut history) = self.history.lock() {
+            if let Some(y) = offset {
+                history.set_scroll(y);
+            }
+            history.push(entry);
+        }
+        self.notify_history();
+    }
+
+    fn notify_history(&self) {
+        if let Ok(history) = self.history.lock() {
+            for listener in self.history_listeners.borrow().iter() {
+                listener(&history);
+            }
+        }
+    }
+
+    /// Returns `true` if `path` is the file currently shown.
+    pub fn is_current_file(&self, path: &Path) -> bool {
+        let current = self.current_file.lock().ok().and_then(|f| f.clone());
+        current.is_some_and(|current| same_file(&current, path))
+    }
+
+    /// Re-renders the current file, if any, using the configured reload mode.
+    pub fn reload(&self) {
+        self.reload_with(self.reload_mode);
+    }
+
+    /// Re-reads and re-renders the current file, if any, keeping the scroll position.
+    pub fn reload_with(&self, mode: ReloadMode) {
+        let current_file = self.current_file.lock().ok().and_then(|f| f.clone());
+        if let Some(file_path) = current_file {
+            info!("Reloading file ({:?}): {}", mode, file_path.display());
+
+            // A reload always reads the file again
+            self.preview.invalidate(&file_path);
+            for listener in self.reloaded_listeners.borrow().iter() {
+                listener(&file_path);
+            }
+
+            let state = self.clone();
+            query_scroll_position(&self.preview.webview, move |offset| {
+                if let Some(y) = offset {
+                    state.remember_scroll(file_path.clone(), y);
+                }
+
+                let target = offset.map(ScrollTarget::Offset);
+                match mode {
+                    ReloadMode::Full => state.show(&file_path, target),
+                    ReloadMode::Patch => {
+                        // Only needed if patching falls back to a full load
+                        if let Ok(mut pending) = state.pending_scroll.lock() {
+                            *pending = target;
+                        }
+                        let pending_scroll = state.pending_scroll.clone();
+                        patch_markdown(&state.preview, &file_path, move |patched| {
+                            if patched {
+                                if let Ok(mut pending) = pending_scroll.lock() {
+                                    *pending = None;
+                                }
+                            }
+                        });
+                    }
+                }
+            });
+        }
+    }
+
+    /// Follows a change of the desktop color scheme unless the user picked a theme.
+    pub fn color_scheme_changed(&self, prefer_dark: bool) {
+        let chosen = self
+            .chosen_theme
+            .lock()
+            .map(|chosen| chosen.is_some())
+            .unwrap_or(false);
+        if !chosen {
+            self.apply_theme(Theme::for_color_scheme(prefer_dark));
+        }
+    }
+
+    /// Loads and applies a theme picked by the user.
+    ///
+    /// From then on the desktop color scheme is no longer followed. If the
+    /// theme files are invalid the error is shown above the document and the
+    /// theme is unchanged.
+    pub fn choose_theme(&self, choice: &ThemeChoice) {
+        match choice.load() {
+            Ok(theme) => {
+                if let Ok(mut chosen) = self.chosen_theme.lock() {
+                    *chosen = Some(choice.clone());
+                }
+                self.apply_theme(theme);
+            }
+            Err(e) => {
+                error!("Failed to load theme '{}': {}", choice.name(), e);
+                self.preview.show_error(
+                    "Failed to Load Theme",
+                    &format!("Could not load theme {}: {}", choice.name(), e),
+                );
+            }
+        }
+    }
+
+    /// Renders the current file again with `theme`, keeping the scroll position.
+    ///
+    /// The source already in memory is used, so the file is not read again.
+    pub fn apply_theme(&self, theme: Theme) {
+        if self.preview.theme() == theme {
+            return;
+        }
+        self.preview.set_theme(theme);
+
+        let current_file = self.current_file.lock().ok().and_then(|f| f.clone());
+        if let Some(file_path) = current_file {
+            let state = self.clone();
+            query_scroll_position(&self.preview.webview, move |offset| {
+                if let Ok(mut pending) = state.pending_scroll.lock() {
+                    *pending = offset.map(ScrollTarget::Offset);
+                }
+                rerender_markdown(&state.preview, &file_path);
+            });
+        }
+    }
+
+    /// Returns where `path` was last scrolled to in this session.
+    fn remembered_scroll(&self, path: &Path) -> Option<ScrollTarget> {
+        self.scroll_positions
+            .lock()
+            .ok()
+            .and_then(|positions| positions.get(path).copied())
+            .map(ScrollTarget::Offset)
+    }
+
+    fn remember_scroll(&self, path: PathBuf, y: f64) {
+        if let Ok(mut positions) = self.scroll_positions.lock() {
+            positions.insert(path, y);
+        }
+    }
+
+    /// Displays `path` and schedules a scroll to `scroll` after loading.
+    fn show(&self, path: &Path, scroll: Option<ScrollTarget>) {
+        if let Ok(mut pending) = self.pending_scroll.lock() {
+            *pending = scroll;
+        }
+        display_markdown(&self.preview, path);
+    }
+}
+
+/// Returns `true` if both paths lead to the same file.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
@@ -0,0 +1,176 @@
+use crate::error::{DogmvError, Result};
+use crate::markdown::{DiagramTools, MarkdownOptions};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of dogmv's directory below the XDG config directory
+const APP_DIR_NAME: &str = "dogmv";
+
+/// Name of the configuration file in the config directory
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// User settings read from `config.toml` in the config directory.
+///
+/// Every setting is optional; a missing file means all defaults.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Syntax names by file name or extension, e.g. `Justfile = "Just"`,
+    /// used for source files and fenced code blocks
+    pub syntax_map: HashMap<String, String>,
+    /// Commands used to render graphviz and plantuml fences
+    pub diagrams: DiagramTools,
+    /// comrak extension, parse and render options
+    pub markdown: MarkdownOptions,
+}
+
+impl Config {
+    /// Loads the configuration file, or the defaults if there is none.
+    pub fn load() -> Result<Self> {
+        match config_dir() {
+            Some(dir) => Self::from_file(&dir.join(CONFIG_FILE_NAME)),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Loads the configuration from `path`, or the defaults if it doesn't exist.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content)
+            .map_err(|reason| DogmvError::InvalidConfig(path.to_path_buf(), reason))
+    }
+
+    fn parse(content: &str) -> std::result::Result<Self, String> {
+        toml::from_str(content).map_err(|e| e.to_string())
+    }
+}
+
+/// Returns dogmv's configuration directory.
+///
+/// This is `$XDG_CONFIG_HOME/dogmv`, or `~/.config/dogmv` if `XDG_CONFIG_HOME`
+/// is unset. The directory is not created.
+pub fn config_dir() -> Option<PathBuf> {
+    config_dir_from(env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME"))
+}
+
+/// Returns the directory user themes (`*.css`, `*.tmTheme`) are loaded from.
+pub fn themes_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("themes"))
+}
+
+/// Returns the directory extra syntax definitions (`*.sublime-syntax`) are loaded from.
+pub fn syntaxes_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("syntaxes"))
+}
+
+fn config_dir_from(xdg_config_home: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    // The XDG spec says relative paths are invalid and must be ignored
+    let base = xdg_config_home
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| {
+            home.map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .map(|home| home.join(".config"))
+        })?;
+    Some(base.join(APP_DIR_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_dir_prefers_xdg_config_home() {
+        assert_eq!(
+            config_dir_from(Some("/xdg".into()), Some("/home/user".into())),
+            Some(PathBuf::from("/xdg/dogmv"))
+        );
+        assert_eq!(
+            config_dir_from(None, Some("/home/user".into())),
+            Some(PathBuf::from("/home/user/.config/dogmv"))
+        );
+    }
+
+    #[test]
+    fn test_config_dir_ignores_relative_paths() {
+        assert_eq!(
+            config_dir_from(Some("relative".into()), Some("/home/user".into())),
+            Some(PathBuf::from("/home/user/.config/dogmv"))
+        );
+        assert_eq!(config_dir_from(None, None), None);
+    }
+
+    #[test]
+    fn test_parse_syntax_map() {
+        let config = Config::parse(
+            r#"
+[syntax_map]
+"flake.lock" = "JSON"
+Justfile = "Just"
+nix = "Nix"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.syntax_map.get("flake.lock").map(String::as_str), Some("JSON"));
+        assert_eq!(config.syntax_map.get("Justfile").map(String::as_str), Some("Just"));
+        assert_eq!(config.syntax_map.len(), 3);
+
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_parse_diagram_tools() {
+        let config = Config::parse(
+            r#"
+[diagrams]
+plantuml = "java -jar /opt/plantuml.jar"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.diagrams.plantuml, "java -jar /opt/plantuml.jar");
+        // Unset commands keep their default
+        assert_eq!(config.diagrams.dot, "dot");
+    }
+
+    #[test]
+    fn test_parse_markdown_options() {
+        let config = Config::parse(
+            r#"
+[markdown.extension]
+superscript = true
+header_ids = "user-content-"
+front_matter_delimiter = false
+
+[markdown.render]
+unsafe = true
+"#,
+        )
+        .unwrap();
+        let markdown = &config.markdown;
+        assert!(markdown.extension.superscript);
+        assert_eq!(markdown.extension.header_ids.as_deref(), Some("user-content-"));
+        assert_eq!(markdown.extension.front_matter_delimiter, None);
+        assert!(markdown.render.unsafe_);
+        // Options that aren't set keep the GFM defaults
+        assert!(markdown.extension.table);
+        assert!(!markdown.parse.smart);
+
+        assert!(Config::parse("[markdown.extension]
+superscrpt = true").is_err());
+    }
+
+    #[test]
+    fn test_parse_reports_invalid_config() {
+        assert!(Config::parse("[syntax_map]\nnix = 1").is_err());
+        // Typos are reported instead of being silently ignored
+        assert!(Config::parse("[syntax-map]").is_err());
+    }
+}
@@ -26,6 +26,12 @@ pub enum DogmvError {
     #[allow(dead_code)]
     RenderingError(String),
 
+    #[error("Invalid config file {0}: {1}")]
+    InvalidConfig(PathBuf, String),
+
+    #[error("Invalid theme file {0}: {1}")]
+    InvalidTheme(PathBuf, String),
+
     #[error("Invalid path: {0}")]
     #[allow(dead_code)]
     InvalidPath(String),
@@ -2,6 +2,9 @@ use log::info;
 use std::env;
 use std::path::{Path, PathBuf};
 
+/// Option comparing two files: `dogmv --diff OLD NEW`
+const DIFF_OPTION: &str = "--diff";
+
 /// Parses command-line arguments and returns the initial file and root directory.
 ///
 /// # Arguments
@@ -14,6 +17,7 @@ use std::path::{Path, PathBuf};
 /// - No arguments: Uses current directory as root, no initial file
 /// - File argument: Opens the file, uses parent directory as root
 /// - Directory argument: Uses directory as root, no initial file
+/// - `--diff OLD NEW`: Opens NEW (see `parse_diff_arguments`), uses its parent directory as root
 ///
 /// # Exits
 /// Exits the process with code 1 if:
@@ -27,6 +31,11 @@ pub fn parse_arguments(args: &[String]) -> (Option<PathBuf>, PathBuf) {
         return (None, current_dir);
     }
 
+    if let Some((_, new)) = parse_diff_arguments(args) {
+        let parent_dir = parent_dir(&new);
+        return (Some(new), parent_dir);
+    }
+
     let arg_path = Path::new(&args[1]);
 
     if !arg_path.exists() {
@@ -36,9 +45,7 @@ pub fn parse_arguments(args: &[String]) -> (Option<PathBuf>, PathBuf) {
 
     if arg_path.is_file() {
         // File specified: open file and use parent directory as root
-        let parent_dir = arg_path.parent()
-            .map(|p| p.to_path_buf())
-            .unwrap_or_else(|| PathBuf::from("."));
+        let parent_dir = parent_dir(arg_path);
         info!("File specified: {:?}, root directory: {:?}", arg_path, parent_dir);
         (Some(arg_path.to_path_buf()), parent_dir)
     } else if arg_path.is_dir() {
@@ -51,6 +58,38 @@ pub fn parse_arguments(args: &[String]) -> (Option<PathBuf>, PathBuf) {
     }
 }
 
+/// Parses `dogmv --diff OLD NEW` and returns the files to compare (old, new).
+///
+/// Returns `None` if the arguments don't start with `--diff`.
+///
+/// # Exits
+/// Exits the process with code 1 if `--diff` isn't followed by two existing files.
+pub fn parse_diff_arguments(args: &[String]) -> Option<(PathBuf, PathBuf)> {
+    if args.get(1).map(String::as_str) != Some(DIFF_OPTION) {
+        return None;
+    }
+
+    let [old, new] = &args[2..] else {
+        eprintln!("Usage: dogmv {} OLD NEW", DIFF_OPTION);
+        std::process::exit(1);
+    };
+    for path in [old, new] {
+        if !Path::new(path).is_file() {
+            eprintln!("Error: File not found: {}", path);
+            std::process::exit(1);
+        }
+    }
+
+    info!("Comparing {:?} with {:?}", new, old);
+    Some((PathBuf::from(old), PathBuf::from(new)))
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    path.parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,4 +101,20 @@ mod tests {
         assert!(file.is_none());
         assert!(root.is_absolute() || root.as_os_str() == ".");
     }
+
+    #[test]
+    fn test_parse_diff_arguments() {
+        let args: Vec<String> = ["dogmv", "--diff", "Cargo.toml", "src/main.rs"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(
+            parse_diff_arguments(&args),
+            Some((PathBuf::from("Cargo.toml"), PathBuf::from("src/main.rs")))
+        );
+        assert_eq!(parse_arguments(&args), (Some(PathBuf::from("src/main.rs")), PathBuf::from("src")));
+
+        let args = vec!["dogmv".to_string(), "Cargo.toml".to_string()];
+        assert_eq!(parse_diff_arguments(&args), None);
+    }
 }
@@ -1,3 +1,5 @@
 pub mod cli;
+pub mod watcher;
 
-pub use cli::parse_arguments;
+pub use cli::{parse_arguments, parse_diff_arguments};
+pub use watcher::{FileWatcher, WatchEvent};
@@ -0,0 +1,360 @@
+use async_channel::Sender;
+use log::{error, info, warn};
+use notify::event::ModifyKind;
+use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Notification sent from the watcher thread to the GTK main loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// The watched file was modified or replaced.
+    Changed(PathBuf),
+    /// The directory watch was lost and must be re-armed with `FileWatcher::rearm`.
+    Rearm,
+}
+
+/// Watches the file currently shown in the preview.
+///
+/// A single `FileWatcher` is owned by the application state and is moved to
+/// whichever file is displayed. The underlying notify watcher (and its
+/// background thread) is created once and reused, so switching files never
+/// leaves an orphaned watcher behind.
+///
+/// The parent directory is watched instead of the file itself. Editors that
+/// save atomically (write a temp file, then rename it over the original)
+/// replace the file's inode, which would silently end an inode-based watch.
+///
+/// Changes are pushed as `WatchEvent`s through an async channel, so the main
+/// loop only wakes up when something actually happened.
+pub struct FileWatcher {
+    watcher: Option<RecommendedWatcher>,
+    watched_dir: Option<PathBuf>,
+    shared: Arc<Mutex<WatchState>>,
+    events: Sender<WatchEvent>,
+}
+
+/// State shared between the notify callback thread and the main thread.
+#[derive(Default)]
+struct WatchState {
+    target: Option<PathBuf>,
+    watched_dir: Option<PathBuf>,
+}
+
+impl FileWatcher {
+    /// Creates an idle watcher that reports changes to `events`.
+    pub fn new(events: Sender<WatchEvent>) -> Self {
+        Self {
+            watcher: None,
+            watched_dir: None,
+            shared: Arc::new(Mutex::new(WatchState::default())),
+            events,
+        }
+    }
+
+    /// Starts watching `path`, stopping the watch on the previous target.
+    pub fn watch(&mut self, path: &Path) {
+        let target = absolute_path(path);
+        let Some(dir) = target.parent().map(Path::to_path_buf) else {
+            warn!("Cannot watch '{}': no parent directory", path.display());
+            return;
+        };
+
+        if self.current_target().as_ref() == Some(&target) {
+            return;
+        }
+
+        if self.watcher.is_none() {
+            self.watcher = self.create_watcher();
+        }
+
+        // Switch the target first so that late events for the old file are ignored
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.target = Some(target.clone());
+            shared.watched_dir = Some(dir.clone());
+        }
+
+        if self.watched_dir.as_ref() != Some(&dir) {
+            self.unwatch_dir();
+            self.watch_dir(&dir);
+        }
+
+        info!("Watching file: {}", target.display());
+    }
+
+    /// Stops watching the current target, if any.
+    pub fn unwatch(&mut self) {
+        self.unwatch_dir();
+
+        if let Ok(mut shared) = self.shared.lock() {
+            if let Some(old) = shared.target.take() {
+                info!("Stopped watching: {}", old.display());
+            }
+            shared.watched_dir = None;
+        }
+    }
+
+    /// Stops watching for good and closes the event channel.
+    ///
+    /// The receiving end sees the channel closed once pending events are
+    /// drained, which ends the future serving it.
+    pub fn close(&mut self) {
+        self.unwatch();
+        self.events.close();
+    }
+
+    /// Re-establishes a lost directory watch.
+    ///
+    /// Returns `false` while the directory does not exist (yet), so the caller
+    /// can retry later. A `Changed` event is sent afterwards because the file
+    /// may have been replaced while the watch was down.
+    pub fn rearm(&mut self) -> bool {
+        let Some((dir, target)) = self
+            .shared
+            .lock()
+            .ok()
+            .and_then(|s| Some((s.watched_dir.clone()?, s.target.clone()?)))
+        else {
+            return true;
+        };
+
+        if !dir.is_dir() {
+            return false;
+        }
+
+        info!("Re-arming file watcher for: {}", dir.display());
+        self.unwatch_dir();
+        if !self.watch_dir(&dir) {
+            return false;
+        }
+
+        if target.exists() {
+            send(&self.events, WatchEvent::Changed(target));
+        }
+        true
+    }
+
+    /// Returns `true` if `path` (as reported in a `WatchEvent`) is the current target.
+    pub fn is_watching(&self, path: &Path) -> bool {
+        self.current_target().as_deref() == Some(path)
+    }
+
+    fn current_target(&self) -> Option<PathBuf> {
+        self.shared.lock().ok().and_then(|shared| shared.target.clone())
+    }
+
+    fn watch_dir(&mut self, dir: &Path) -> bool {
+        let Some(watcher) = self.watcher.as_mut() else {
+            return false;
+        };
+
+        match watcher.watch(dir, RecursiveMode::NonRecursive) {
+            Ok(()) => {
+                self.watched_dir = Some(dir.to_path_buf());
+                true
+            }
+            Err(e) => {
+                error!("Failed to watch directory '{}': {}", dir.display(), e);
+                send(&self.events, WatchEvent::Rearm);
+                false
+            }
+        }
+    }
+
+    fn unwatch_dir(&mut self) {
+        if let Some(old) = self.watched_dir.take() {
+            if let Some(watcher) = self.watcher.as_mut() {
+                // Fails harmlessly if the directory no longer exists
+                if let Err(e) = watcher.unwatch(&old) {
+                    warn!("Failed to unwatch directory '{}': {}", old.display(), e);
+                }
+            }
+        }
+    }
+
+    fn create_watcher(&self) -> Option<RecommendedWatcher> {
+        let shared = Arc::clone(&self.shared);
+        let events = self.events.clone();
+
+        let result = RecommendedWatcher::new(
+            move |res: std::result::Result<Event, notify::Error>| {
+                let watch_event = match res {
+                    Ok(event) => match shared.lock() {
+                        Ok(shared) => handle_event(&shared, &event),
+                        Err(_) => None,
+                    },
+                    Err(e) => {
+                        warn!("File watch error: {}", e);
+                        Some(WatchEvent::Rearm)
+                    }
+                };
+                if let Some(watch_event) = watch_event {
+                    send(&events, watch_event);
+                }
+            },
+            Config::default().with_poll_interval(Duration::from_secs(1)),
+        );
+
+        match result {
+            Ok(watcher) => {
+                info!("File watcher started successfully");
+                Some(watcher)
+            }
+            Err(e) => {
+                error!("Failed to create file watcher: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.unwatch();
+    }
+}
+
+/// Translates a single event from the watched directory into a `WatchEvent`.
+fn handle_event(shared: &WatchState, event: &Event) -> Option<WatchEvent> {
+    let target = shared.target.as_ref()?;
+
+    // The watched directory itself went away: the watch is dead until re-armed
+    if shared.watched_dir.as_ref().is_some_and(|dir| event.paths.contains(dir))
+        && (event.kind.is_remove() || matches!(event.kind, EventKind::Modify(ModifyKind::Name(_))))
+    {
+        warn!("Watched directory was removed or renamed");
+        return Some(WatchEvent::Rearm);
+    }
+
+    if !event.paths.contains(target) || !is_relevant(&event.kind) {
+        return None;
+    }
+
+    if target.exists() {
+        info!("File changed: {:?}", event.kind);
+        Some(WatchEvent::Changed(target.clone()))
+    } else {
+        // Atomic saves remove or rename the original before the new file
+        // arrives; the following create/rename event triggers the reload.
+        info!("File removed or renamed away, waiting for it to reappear: {:?}", event.kind);
+        None
+    }
+}
+
+fn send(events: &Sender<WatchEvent>, event: WatchEvent) {
+    // The channel is unbounded, so this only fails once the receiver is gone
+    if let Err(e) = events.try_send(event) {
+        warn!("Failed to send file event: {}", e);
+    }
+}
+
+/// Returns `true` for events that can change the file's content.
+fn is_relevant(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Create(_) | EventKind::Remove(_) => true,
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Modify(_) => true,
+        EventKind::Access(_) | EventKind::Any | EventKind::Other => false,
+    }
+}
+
+/// Makes `path` absolute without resolving the file name itself.
+///
+/// Events are reported as `<watched dir>/<name>`, so the directory part is
+/// canonicalized to match them while the file name is kept as given.
+fn absolute_path(path: &Path) -> PathBuf {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir()
+            .map(|dir| dir.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    };
+
+    match (absolute.parent(), absolute.file_name()) {
+        (Some(parent), Some(name)) => fs::canonicalize(parent)
+            .map(|parent| parent.join(name))
+            .unwrap_or(absolute),
+        _ => absolute,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange, MetadataKind, RemoveKind, RenameMode};
+
+    fn state_for(target: &Path) -> WatchState {
+        WatchState {
+            target: Some(target.to_path_buf()),
+            watched_dir: target.parent().map(Path::to_path_buf),
+        }
+    }
+
+    fn event(kind: EventKind, paths: &[&Path]) -> Event {
+        paths
+            .iter()
+            .fold(Event::new(kind), |event, path| event.add_path(path.to_path_buf()))
+    }
+
+    #[test]
+    fn test_rename_over_target_is_a_change() {
+        // Cargo.toml always exists when tests run
+        let target = absolute_path(Path::new("Cargo.toml"));
+        let tmp = target.with_file_name("Cargo.toml.tmp");
+        let state = state_for(&target);
+
+        let kind = EventKind::Modify(ModifyKind::Name(RenameMode::Both));
+        assert_eq!(
+            handle_event(&state, &event(kind, &[&tmp, &target])),
+            Some(WatchEvent::Changed(target.clone()))
+        );
+    }
+
+    #[test]
+    fn test_events_for_other_files_are_ignored() {
+        let target = absolute_path(Path::new("Cargo.toml"));
+        let other = target.with_file_name("other.md");
+        let state = state_for(&target);
+
+        let kind = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        assert_eq!(handle_event(&state, &event(kind, &[&other])), None);
+    }
+
+    #[test]
+    fn test_metadata_and_create_events() {
+        let target = absolute_path(Path::new("Cargo.toml"));
+        let state = state_for(&target);
+
+        let kind = EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions));
+        assert_eq!(handle_event(&state, &event(kind, &[&target])), None);
+
+        let kind = EventKind::Create(CreateKind::File);
+        assert_eq!(
+            handle_event(&state, &event(kind, &[&target])),
+            Some(WatchEvent::Changed(target.clone()))
+        );
+    }
+
+    #[test]
+    fn test_removed_target_waits_for_replacement() {
+        let target = absolute_path(Path::new("does-not-exist.md"));
+        let state = state_for(&target);
+
+        let kind = EventKind::Remove(RemoveKind::File);
+        assert_eq!(handle_event(&state, &event(kind, &[&target])), None);
+    }
+
+    #[test]
+    fn test_removed_directory_needs_rearm() {
+        let target = absolute_path(Path::new("Cargo.toml"));
+        let dir = target.parent().unwrap().to_path_buf();
+        let state = state_for(&target);
+
+        let kind = EventKind::Remove(RemoveKind::Folder);
+        assert_eq!(handle_event(&state, &event(kind, &[&dir])), Some(WatchEvent::Rearm));
+    }
+}
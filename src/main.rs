@@ -1,27 +1,40 @@
+mod app_state;
+mod config;
 mod error;
 mod file_system;
 mod markdown;
 mod models;
 mod ui;
 
+use app_state::AppState;
 use ctor::ctor;
-use file_system::parse_arguments;
+use file_system::{parse_arguments, parse_diff_arguments};
+use config::Config;
+use markdown::{available_themes, HighlightEngine, MarkdownOptions, SyntaxConfig, ThemeChoice};
+use models::History;
 use ui::{
-    create_tree_view, display_markdown, display_welcome_message, setup_file_selection_handler,
-    setup_toggle_button, setup_toggle_button_css,
+    create_outline_view, create_tree_view, setup_asset_scheme, setup_file_selection_handler,
+    setup_link_navigation, setup_outline_handlers, setup_scroll_restore, setup_toggle_button,
+    setup_toggle_button_css, setup_tree_sync, watch_color_scheme, ReloadMode, SyncScroll, Tabs,
 };
 use gtk4::prelude::*;
-use gtk4::{gdk, gio, glib, Application, ApplicationWindow, EventControllerKey, FileChooserNative, FileChooserAction, FileFilter, ResponseType, HeaderBar, Paned, Orientation, Box as GtkBox, Button, Label};
-use log::{error, info, warn};
-use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use gtk4::{gdk, gio, glib, Application, ApplicationWindow, EventControllerKey, EventSequenceState, FileChooserNative, FileChooserAction, FileFilter, GestureClick, ResponseType, HeaderBar, Paned, Orientation, PropagationPhase, Box as GtkBox, Button, CheckButton, DropDown, Label, MenuButton, Popover, ToggleButton};
+use log::{info, warn};
 use std::env;
-use std::path::{Path, PathBuf};
-use std::sync::{mpsc, Arc, Mutex};
+use std::path::PathBuf;
+use std::rc::Rc;
 use std::time::Duration;
-use webkit6::WebView;
+use webkit6::prelude::*;
+use webkit6::LoadEvent;
 
 const APP_ID: &str = "com.github.dogmv";
 
+/// Window title while no file is shown
+const APP_TITLE: &str = "dogmv - Markdown Viewer";
+
+/// Default time to wait for a burst of file events to settle before reloading
+const DEFAULT_RELOAD_DEBOUNCE: Duration = Duration::from_millis(50);
+
 // Initialize environment variables before main() is called
 // This is necessary to prevent GSettings schema errors in GTK4's FileChooser
 #[ctor]
@@ -32,14 +45,6 @@ fn init_environment() {
     std::env::set_var("GSETTINGS_BACKEND", "memory");
 }
 
-// アプリケーション状態を保持する構造体
-#[derive(Clone)]
-struct AppState {
-    current_file: Arc<Mutex<Option<PathBuf>>>,
-    root_dir: Arc<Mutex<Option<PathBuf>>>,
-    webview: WebView,
-}
-
 fn main() {
 
     // Initialize logger
@@ -68,11 +73,25 @@ fn build_ui(app: &Application) {
     // Parse CLI arguments
     let args: Vec<String> = env::args().collect();
     let (initial_file, root_dir) = parse_arguments(&args);
+    let diff_files = parse_diff_arguments(&args);
+
+    // Load user configuration
+    let user_config = Config::load().unwrap_or_else(|e| {
+        warn!("Using default configuration: {}", e);
+        Config::default()
+    });
+    HighlightEngine::configure(SyntaxConfig {
+        syntaxes_dir: config::syntaxes_dir(),
+        syntax_map: user_config.syntax_map,
+    });
+    markdown::diagrams::configure(user_config.diagrams);
+    MarkdownOptions::configure(user_config.markdown);
 
     // Create HeaderBar (CSD)
     let header_bar = HeaderBar::new();
     header_bar.set_show_title_buttons(true);
-    header_bar.set_title_widget(Some(&Label::new(Some("dogmv - Markdown Viewer"))));
+    let title_label = Label::new(Some(APP_TITLE));
+    header_bar.set_title_widget(Some(&title_label));
 
     // Create main window
     let window = ApplicationWindow::builder()
@@ -82,10 +101,13 @@ fn build_ui(app: &Application) {
         .build();
 
     window.set_titlebar(Some(&header_bar));
+    window.set_title(Some(APP_TITLE));
+
+    // Serve bundled assets (mermaid) to the preview
+    setup_asset_scheme();
 
-    // Create WebView for preview
-    info!("Creating WebView");
-    let webview = WebView::new();
+    // Create preview tabs (each a WebView with loading indicator)
+    let tabs = Tabs::new(&root_dir, reload_mode(), reload_debounce());
 
     // Create sidebar toggle button (initially showing close icon since sidebar is visible)
     let toggle_button = Button::from_icon_name("pan-start-symbolic");
@@ -93,8 +115,22 @@ fn build_ui(app: &Application) {
     toggle_button.add_css_class("flat"); // Remove button border
     toggle_button.add_css_class("flat-toggle"); // Remove border on hover/active
 
-    // Create tree view (initially visible)
-    let (tree_scroll, selection_model) = create_tree_view(&root_dir);
+    // Create tree view (initially visible); middle click opens a new tab
+    let tabs_clone = tabs.clone();
+    let (tree_scroll, selection_model) = create_tree_view(
+        &root_dir,
+        Rc::new(move |path| {
+            tabs_clone.open_tab(Some(path));
+        }),
+    );
+
+    // Create outline panel below the tree
+    let (outline_box, outline_list) = create_outline_view();
+    let sidebar_paned = Paned::new(Orientation::Vertical);
+    sidebar_paned.set_start_child(Some(&tree_scroll));
+    sidebar_paned.set_end_child(Some(&outline_box));
+    sidebar_paned.set_position(420);
+    sidebar_paned.set_vexpand(true);
 
     // Create toggle button box (right-aligned)
     let toggle_box = GtkBox::new(Orientation::Horizontal, 0);
@@ -104,37 +140,54 @@ fn build_ui(app: &Application) {
     // Create sidebar box
     let sidebar_box = GtkBox::new(Orientation::Vertical, 0);
     sidebar_box.append(&toggle_box);
-    sidebar_box.append(&tree_scroll);
+    sidebar_box.append(&sidebar_paned);
 
     // Create Paned layout
     let paned = Paned::new(Orientation::Horizontal);
     paned.set_start_child(Some(&sidebar_box));
-    paned.set_end_child(Some(&webview));
+    paned.set_end_child(Some(tabs.widget()));
     paned.set_position(250); // Initial width: 250px
 
-    // Setup app state
-    let app_state = AppState {
-        current_file: Arc::new(Mutex::new(initial_file.clone())),
-        root_dir: Arc::new(Mutex::new(Some(root_dir.clone()))),
-        webview: webview.clone(),
-    };
-
     // Setup toggle button click handler
-    setup_toggle_button(&toggle_button, &tree_scroll, &paned);
+    setup_toggle_button(&toggle_button, &sidebar_paned, &paned);
 
     // Setup file selection handler
-    setup_file_selection_handler(&selection_model, &webview, app_state.current_file.clone());
-
-    // Display initial content
-    if let Some(ref file_path) = initial_file {
-        display_markdown(&webview, file_path);
-        setup_file_watcher(&webview, file_path);
-    } else {
-        display_welcome_message(&webview);
-    }
+    setup_file_selection_handler(&selection_model, &tabs);
+    setup_tree_sync(&selection_model, &tabs);
+
+    tabs.connect_tab_added(|state| {
+        // Open links to local documents in the preview
+        setup_link_navigation(state);
+
+        // Restore scroll positions after (re)loads
+        setup_scroll_restore(&state.preview.webview, state.pending_scroll.clone());
+    });
+
+    // Keep the outline in sync with the preview
+    setup_outline_handlers(&outline_list, &tabs);
+
+    // Setup back/forward buttons and mouse buttons
+    setup_history_controls(&window, &header_bar, &tabs);
+
+    // Show the document title in the window title
+    setup_window_title(&window, &title_label, &tabs);
 
     // Setup keyboard shortcuts
-    setup_keyboard_shortcuts(&window, &app_state);
+    setup_keyboard_shortcuts(&window, &tabs);
+
+    // Display initial content (or the files given to --diff) in the first tab
+    match diff_files {
+        Some((old, new)) => tabs.open_tab(None).open_diff(&old, &new),
+        None => {
+            tabs.open_tab(initial_file.as_deref());
+        }
+    }
+
+    // Setup theme picker and light/dark theme switching
+    setup_theme_controls(&header_bar, &tabs);
+
+    // Setup split view, scroll sync and compare menu
+    setup_split_controls(&window, &header_bar, &tabs);
 
     // Add layout to window
     window.set_child(Some(&paned));
@@ -143,111 +196,424 @@ fn build_ui(app: &Application) {
     window.present();
 }
 
-fn setup_file_watcher(webview: &WebView, file_path: &Path) {
-    info!("Setting up file watcher for: {}", file_path.display());
+/// Returns the reload debounce window, overridable with `DOGMV_DEBOUNCE_MS`.
+fn reload_debounce() -> Duration {
+    match env::var("DOGMV_DEBOUNCE_MS") {
+        Ok(value) => match value.parse::<u64>() {
+            Ok(ms) => Duration::from_millis(ms),
+            Err(_) => {
+                warn!("Ignoring invalid DOGMV_DEBOUNCE_MS value: {}", value);
+                DEFAULT_RELOAD_DEBOUNCE
+            }
+        },
+        Err(_) => DEFAULT_RELOAD_DEBOUNCE,
+    }
+}
 
-    let file_path = file_path.to_path_buf();
-    let file_path_for_thread = file_path.clone();
+/// Returns how live reloads update the preview, overridable with `DOGMV_RELOAD_MODE`.
+fn reload_mode() -> ReloadMode {
+    match env::var("DOGMV_RELOAD_MODE") {
+        Ok(value) => ReloadMode::from_name(&value).unwrap_or_else(|| {
+            warn!("Ignoring invalid DOGMV_RELOAD_MODE value: {}", value);
+            ReloadMode::Patch
+        }),
+        Err(_) => ReloadMode::Patch,
+    }
+}
+
+/// Keeps the window title in sync with the document shown in the current tab.
+///
+/// Documents with a front matter `title` are shown by their title (the page
+/// title set by the rendered document), everything else by its file name.
+fn setup_window_title(window: &ApplicationWindow, title_label: &Label, tabs: &Tabs) {
+    let update_title: Rc<dyn Fn()> = {
+        let window = window.clone();
+        let title_label = title_label.clone();
+        let tabs = tabs.clone();
+        Rc::new(move || {
+            let state = tabs.current();
+            let page_title = state
+                .as_ref()
+                .and_then(|state| state.preview.webview.title())
+                .map(|title| title.to_string())
+                .filter(|title| !title.is_empty());
+            let file_name = state
+                .as_ref()
+                .and_then(|state| state.current_file.lock().ok().and_then(|file| file.clone()))
+                .and_then(|path| {
+                    path.file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                });
+            let title = match page_title.or(file_name) {
+                Some(document) => format!("{} - dogmv", document),
+                None => APP_TITLE.to_string(),
+            };
+            title_label.set_text(&title);
+            window.set_title(Some(&title));
+        })
+    };
 
-    // Use Arc<Mutex<bool>> to signal file changes
-    let file_changed = Arc::new(Mutex::new(false));
-    let file_changed_clone = Arc::clone(&file_changed);
+    let on_added = Rc::clone(&update_title);
+    tabs.connect_tab_added(move |state| {
+        let webview = &state.preview.webview;
+        let on_title = Rc::clone(&on_added);
+        webview.connect_title_notify(move |_| on_title());
+        // The title doesn't change between two pages without one
+        let on_load = Rc::clone(&on_added);
+        webview.connect_load_changed(move |_, event| {
+            if event == LoadEvent::Finished {
+                on_load();
+            }
+        });
+    });
+    tabs.connect_switched(move |_| update_title());
+}
 
-    // Spawn a thread to handle file watching
-    std::thread::spawn(move || {
-        // Create a channel for file system events
-        let (event_tx, event_rx) = mpsc::channel::<std::result::Result<Event, notify::Error>>();
+/// Adds back/forward buttons to the header bar and handles the mouse's
+/// back/forward buttons anywhere in the window.
+///
+/// Both act on the history of the current tab.
+fn setup_history_controls(window: &ApplicationWindow, header_bar: &HeaderBar, tabs: &Tabs) {
+    info!("Setting up history controls");
+
+    let back_button = Button::from_icon_name("go-previous-symbolic");
+    back_button.set_tooltip_text(Some("戻る (Alt+←)"));
+    back_button.add_css_class("flat");
+    back_button.set_sensitive(false);
+
+    let forward_button = Button::from_icon_name("go-next-symbolic");
+    forward_button.set_tooltip_text(Some("進む (Alt+→)"));
+    forward_button.add_css_class("flat");
+    forward_button.set_sensitive(false);
+
+    let tabs_clone = tabs.clone();
+    back_button.connect_clicked(move |_| {
+        if let Some(state) = tabs_clone.current() {
+            state.go_back();
+        }
+    });
+    let tabs_clone = tabs.clone();
+    forward_button.connect_clicked(move |_| {
+        if let Some(state) = tabs_clone.current() {
+            state.go_forward();
+        }
+    });
 
-        // Create a watcher
-        let mut watcher = match RecommendedWatcher::new(
-            move |res| {
-                if let Err(e) = event_tx.send(res) {
-                    warn!("Failed to send file event: {}", e);
-                }
-            },
-            Config::default().with_poll_interval(Duration::from_secs(1)),
-        ) {
-            Ok(w) => w,
-            Err(e) => {
-                error!("Failed to create file watcher: {}", e);
-                return;
+    let update_buttons: Rc<dyn Fn(&History)> = {
+        let back_weak = back_button.downgrade();
+        let forward_weak = forward_button.downgrade();
+        Rc::new(move |history| {
+            if let Some(button) = back_weak.upgrade() {
+                button.set_sensitive(history.can_go_back());
             }
-        };
+            if let Some(button) = forward_weak.upgrade() {
+                button.set_sensitive(history.can_go_forward());
+            }
+        })
+    };
 
-        // Watch the file
-        if let Err(e) = watcher.watch(&file_path_for_thread, RecursiveMode::NonRecursive) {
-            error!("Failed to watch file: {}", e);
-            return;
+    let tabs_clone = tabs.clone();
+    let on_added = Rc::clone(&update_buttons);
+    tabs.connect_tab_added(move |state| {
+        let tabs = tabs_clone.clone();
+        let tab = state.downgrade();
+        let update = Rc::clone(&on_added);
+        state.connect_history_changed(move |history| {
+            if tab.upgrade().is_some_and(|tab| tabs.is_current(&tab)) {
+                update(history);
+            }
+        });
+    });
+    tabs.connect_switched(move |state| {
+        if let Ok(history) = state.history.lock() {
+            update_buttons(&history);
         }
+    });
 
-        info!("File watcher started successfully");
+    header_bar.pack_start(&back_button);
+    header_bar.pack_start(&forward_button);
 
-        // Keep watcher alive and handle events
-        for res in event_rx {
-            match res {
-                Ok(event) => {
-                    // Check if it's a modification event
-                    if event.kind.is_modify() || event.kind.is_create() {
-                        info!("File changed: {:?}", event.kind);
+    // Mouse buttons 8 and 9; handled before the WebView sees them
+    let gesture = GestureClick::new();
+    gesture.set_button(0);
+    gesture.set_propagation_phase(PropagationPhase::Capture);
+    let tabs_clone = tabs.clone();
+    gesture.connect_pressed(move |gesture, _, _, _| {
+        let Some(state) = tabs_clone.current() else {
+            return;
+        };
+        match gesture.current_button() {
+            8 => state.go_back(),
+            9 => state.go_forward(),
+            _ => return,
+        }
+        gesture.set_state(EventSequenceState::Claimed);
+    });
+    window.add_controller(gesture);
+}
 
-                        // Set the flag
-                        if let Ok(mut changed) = file_changed_clone.lock() {
-                            *changed = true;
-                        }
+/// Adds the theme picker and the dark theme toggle to the header bar.
+///
+/// The picker lists the built-in themes and the user's themes from the
+/// config directory. Both widgets always show the theme in use, whether it
+/// was picked by the user or follows the desktop color scheme. The theme
+/// applies to all tabs.
+fn setup_theme_controls(header_bar: &HeaderBar, tabs: &Tabs) {
+    info!("Setting up theme controls");
+
+    let themes_dir = config::themes_dir();
+    let choices = available_themes(themes_dir.as_deref());
+    let names: Vec<&str> = choices.iter().map(|choice| choice.name()).collect();
+    // Themes are told apart by id; a user theme may be named like a built-in one
+    let ids: Vec<String> = choices.iter().map(ThemeChoice::id).collect();
+
+    let theme_picker = DropDown::from_strings(&names);
+    theme_picker.set_tooltip_text(Some("テーマ選択"));
+
+    let theme_button = ToggleButton::new();
+    theme_button.set_icon_name("weather-clear-night-symbolic");
+    theme_button.set_tooltip_text(Some("ダークテーマ切り替え"));
+    theme_button.add_css_class("flat");
+
+    // Theme of the current tab; all tabs use the same one
+    let current_theme = {
+        let tabs = tabs.clone();
+        move || tabs.current().map(|state| state.preview.theme()).unwrap_or_default()
+    };
 
-                        // Small delay to avoid multiple rapid reloads
-                        std::thread::sleep(Duration::from_millis(100));
-                    }
-                }
-                Err(e) => {
-                    warn!("File watch error: {}", e);
+    // Shows the theme in use; the handlers below ignore these programmatic changes
+    let sync_controls: Rc<dyn Fn()> = {
+        let current_theme = current_theme.clone();
+        let picker_weak = theme_picker.downgrade();
+        let button_weak = theme_button.downgrade();
+        Rc::new(move || {
+            let theme = current_theme();
+            if let Some(picker) = picker_weak.upgrade() {
+                if let Some(index) = ids.iter().position(|id| *id == theme.name) {
+                    picker.set_selected(index as u32);
                 }
             }
+            if let Some(button) = button_weak.upgrade() {
+                button.set_active(theme.dark);
+            }
+        })
+    };
+    sync_controls();
+
+    let tabs_clone = tabs.clone();
+    let theme = current_theme.clone();
+    let sync = Rc::clone(&sync_controls);
+    theme_picker.connect_selected_notify(move |picker| {
+        if let Some(choice) = choices.get(picker.selected() as usize) {
+            if choice.id() != theme().name {
+                tabs_clone.choose_theme(choice);
+                sync();
+            }
         }
     });
 
-    // Setup a periodic check on the main thread
-    let webview_clone = webview.clone();
+    let tabs_clone = tabs.clone();
+    let sync = Rc::clone(&sync_controls);
+    theme_button.connect_toggled(move |button| {
+        if button.is_active() != current_theme().dark {
+            let choice = if button.is_active() {
+                ThemeChoice::Dark
+            } else {
+                ThemeChoice::Light
+            };
+            tabs_clone.choose_theme(&choice);
+            sync();
+        }
+    });
+
+    header_bar.pack_end(&theme_button);
+    header_bar.pack_end(&theme_picker);
+
+    // Follow the desktop color scheme until a theme is picked
+    let tabs_clone = tabs.clone();
+    let subscription = watch_color_scheme(move |dark| {
+        tabs_clone.color_scheme_changed(dark);
+        sync_controls();
+    });
+    if let Some(state) = tabs.current() {
+        if let Ok(mut current) = state.color_scheme_subscription.lock() {
+            *current = subscription;
+        }
+    }
+}
 
-    glib::timeout_add_local(Duration::from_millis(500), move || {
-        // Check if file has changed
-        if let Ok(mut changed) = file_changed.lock() {
-            if *changed {
-                info!("Reloading file: {}", file_path.display());
-                display_markdown(&webview_clone, &file_path);
-                *changed = false;
+/// Adds the split view menu to the header bar.
+///
+/// The preview area can be split side by side or on top of each other, and
+/// the two panes can scroll together by relative position or by headings.
+/// The menu also compares the current document with another file.
+fn setup_split_controls(window: &ApplicationWindow, header_bar: &HeaderBar, tabs: &Tabs) {
+    info!("Setting up split view controls");
+
+    let split_title = Label::new(Some("分割表示"));
+    split_title.set_xalign(0.0);
+    split_title.add_css_class("heading");
+    let no_split = CheckButton::with_label("分割しない");
+    let side_by_side = CheckButton::with_label("左右に分割");
+    side_by_side.set_group(Some(&no_split));
+    let stacked = CheckButton::with_label("上下に分割");
+    stacked.set_group(Some(&no_split));
+    no_split.set_active(true);
+
+    let sync_title = Label::new(Some("スクロール同期"));
+    sync_title.set_xalign(0.0);
+    sync_title.set_margin_top(6);
+    sync_title.add_css_class("heading");
+    let sync_off = CheckButton::with_label("同期しない");
+    let sync_position = CheckButton::with_label("相対位置");
+    sync_position.set_group(Some(&sync_off));
+    let sync_headings = CheckButton::with_label("見出し");
+    sync_headings.set_group(Some(&sync_off));
+    sync_off.set_active(true);
+
+    for (button, orientation) in [
+        (&no_split, None),
+        (&side_by_side, Some(Orientation::Horizontal)),
+        (&stacked, Some(Orientation::Vertical)),
+    ] {
+        let tabs = tabs.clone();
+        button.connect_toggled(move |button| {
+            if button.is_active() && tabs.split_orientation() != orientation {
+                tabs.set_split(orientation);
+            }
+        });
+    }
+    for (button, mode) in [
+        (&sync_off, SyncScroll::Off),
+        (&sync_position, SyncScroll::Position),
+        (&sync_headings, SyncScroll::Headings),
+    ] {
+        let tabs = tabs.clone();
+        button.connect_toggled(move |button| {
+            if button.is_active() {
+                tabs.set_sync_scroll(mode);
             }
+        });
+    }
+
+    // The split pane can also be closed with Ctrl+W
+    let no_split_weak = no_split.downgrade();
+    let side_by_side_weak = side_by_side.downgrade();
+    let stacked_weak = stacked.downgrade();
+    tabs.connect_split_changed(move |orientation| {
+        let button = match orientation {
+            None => no_split_weak.upgrade(),
+            Some(Orientation::Vertical) => stacked_weak.upgrade(),
+            Some(_) => side_by_side_weak.upgrade(),
+        };
+        if let Some(button) = button {
+            button.set_active(true);
+        }
+    });
+
+    let compare_button = Button::with_label("別のファイルと比較…");
+    compare_button.add_css_class("flat");
+    compare_button.set_margin_top(6);
+
+    let menu_box = GtkBox::new(Orientation::Vertical, 3);
+    for widget in [
+        split_title.upcast_ref::<gtk4::Widget>(),
+        no_split.upcast_ref(),
+        side_by_side.upcast_ref(),
+        stacked.upcast_ref(),
+        sync_title.upcast_ref(),
+        sync_off.upcast_ref(),
+        sync_position.upcast_ref(),
+        sync_headings.upcast_ref(),
+        compare_button.upcast_ref(),
+    ] {
+        menu_box.append(widget);
+    }
+
+    let popover = Popover::new();
+    popover.set_child(Some(&menu_box));
+
+    let window_weak = window.downgrade();
+    let popover_weak = popover.downgrade();
+    let tabs_clone = tabs.clone();
+    compare_button.connect_clicked(move |_| {
+        if let Some(popover) = popover_weak.upgrade() {
+            popover.popdown();
+        }
+        if let (Some(window), Some(state)) = (window_weak.upgrade(), tabs_clone.current()) {
+            compare_file_dialog(&window, &state);
         }
-        glib::ControlFlow::Continue
     });
+
+    let split_button = MenuButton::new();
+    split_button.set_icon_name("view-dual-symbolic");
+    split_button.set_tooltip_text(Some("分割表示"));
+    split_button.set_popover(Some(&popover));
+    header_bar.pack_end(&split_button);
 }
 
-fn setup_keyboard_shortcuts(window: &ApplicationWindow, state: &AppState) {
+/// Attaches the keyboard shortcuts to the WebView of every tab.
+fn setup_keyboard_shortcuts(window: &ApplicationWindow, tabs: &Tabs) {
     info!("Setting up keyboard shortcuts");
 
+    let window_weak = window.downgrade();
+    let tabs_clone = tabs.clone();
+    tabs.connect_tab_added(move |state| {
+        if let Some(window) = window_weak.upgrade() {
+            // Attach controller to WebView
+            let controller = create_shortcut_controller(&window, &tabs_clone);
+            state.preview.webview.add_controller(controller);
+            info!("Keyboard controller attached to WebView");
+        }
+    });
+}
+
+fn create_shortcut_controller(window: &ApplicationWindow, tabs: &Tabs) -> EventControllerKey {
     let controller = EventControllerKey::new();
 
     let app_weak = window.application().and_then(|app| Some(app.downgrade()));
     let window_weak = window.downgrade();
-    let state_clone = state.clone();
+    let tabs = tabs.clone();
 
     controller.connect_key_pressed(move |_, key, _keycode, modifier| {
+        let Some(state) = tabs.current() else {
+            return glib::Propagation::Proceed;
+        };
+
+        // Alt+Left/Right: Back/forward
+        if modifier.contains(gdk::ModifierType::ALT_MASK) {
+            match key {
+                gdk::Key::Left => {
+                    state.go_back();
+                    return glib::Propagation::Stop;
+                }
+                gdk::Key::Right => {
+                    state.go_forward();
+                    return glib::Propagation::Stop;
+                }
+                _ => {}
+            }
+        }
+
         // Check for Ctrl key
         if !modifier.contains(gdk::ModifierType::CONTROL_MASK) {
             return glib::Propagation::Proceed;
         }
 
+        // Ctrl+Tab / Ctrl+Shift+Tab: Next/previous tab
+        if matches!(key, gdk::Key::Tab | gdk::Key::ISO_Left_Tab) {
+            let backwards = key == gdk::Key::ISO_Left_Tab
+                || modifier.contains(gdk::ModifierType::SHIFT_MASK);
+            tabs.cycle(backwards);
+            return glib::Propagation::Stop;
+        }
+
         // Use to_unicode() to get the character
         if let Some(ch) = key.to_unicode() {
             match ch {
                 'r' | 'R' => {
-                    // Ctrl+R: Reload
-                    if let Ok(current_file) = state_clone.current_file.lock() {
-                        if let Some(ref file_path) = *current_file {
-                            info!("Reloading file: {}", file_path.display());
-                            display_markdown(&state_clone.webview, file_path);
-                        }
-                    }
+                    // Ctrl+R: Reload (always regenerates the whole page)
+                    state.reload_with(ReloadMode::Full);
                     return glib::Propagation::Stop;
                 }
                 'q' | 'Q' => {
@@ -262,10 +628,21 @@ fn setup_keyboard_shortcuts(window: &ApplicationWindow, state: &AppState) {
                     // Ctrl+O: Open file
                     info!("Opening file dialog");
                     if let Some(window) = window_weak.upgrade() {
-                        open_file_dialog(&window, &state_clone);
+                        open_file_dialog(&window, &state);
                     }
                     return glib::Propagation::Stop;
                 }
+                't' | 'T' => {
+                    // Ctrl+T: New tab
+                    info!("Opening new tab");
+                    tabs.open_tab(None);
+                    return glib::Propagation::Stop;
+                }
+                'w' | 'W' => {
+                    // Ctrl+W: Close tab
+                    tabs.close_current();
+                    return glib::Propagation::Stop;
+                }
                 _ => {}
             }
         }
@@ -273,16 +650,48 @@ fn setup_keyboard_shortcuts(window: &ApplicationWindow, state: &AppState) {
         glib::Propagation::Proceed
     });
 
-    // Attach controller to WebView
-    state.webview.add_controller(controller);
-    info!("Keyboard controller attached to WebView");
+    controller
 }
 
 fn open_file_dialog(window: &ApplicationWindow, state: &AppState) {
     info!("Opening file dialog");
 
+    let state_clone = state.clone();
+    choose_file(window, "Open File", move |path| {
+        // Update root directory to parent of selected file
+        if let Some(parent) = path.parent() {
+            if let Ok(mut root_dir) = state_clone.root_dir.lock() {
+                *root_dir = Some(parent.to_path_buf());
+            }
+            // TODO: Update tree view to show new root directory
+        }
+
+        // Update current file, watcher and preview
+        state_clone.open_file(&path);
+    });
+}
+
+/// Lets the user pick a file to compare the current document with.
+///
+/// The current document is then shown as a rendered diff against the file
+/// picked, as with `dogmv --diff`.
+fn compare_file_dialog(window: &ApplicationWindow, state: &AppState) {
+    let Some(current) = state.current_file.lock().ok().and_then(|f| f.clone()) else {
+        info!("No document open to compare");
+        return;
+    };
+    info!("Opening compare dialog for {}", current.display());
+
+    let state_clone = state.clone();
+    choose_file(window, "Compare With", move |old| {
+        state_clone.open_diff(&old, &current);
+    });
+}
+
+/// Shows a file chooser titled `title` and calls `on_chosen` with the file picked.
+fn choose_file<F: Fn(PathBuf) + 'static>(window: &ApplicationWindow, title: &str, on_chosen: F) {
     let dialog = FileChooserNative::new(
-        Some("Open File"),
+        Some(title),
         Some(window),
         FileChooserAction::Open,
         Some("_Open"),
@@ -313,29 +722,11 @@ fn open_file_dialog(window: &ApplicationWindow, state: &AppState) {
     all_filter.set_name(Some("All files"));
     dialog.add_filter(&all_filter);
 
-    let state_clone = state.clone();
-
     dialog.connect_response(move |dialog, response| {
         if response == ResponseType::Accept {
-            if let Some(file) = dialog.file() {
-                if let Some(path) = file.path() {
-                    info!("Selected file: {}", path.display());
-
-                    // Update current file
-                    if let Ok(mut current_file) = state_clone.current_file.lock() {
-                        *current_file = Some(path.clone());
-                    }
-
-                    // Update root directory to parent of selected file
-                    if let Some(parent) = path.parent() {
-                        if let Ok(mut root_dir) = state_clone.root_dir.lock() {
-                            *root_dir = Some(parent.to_path_buf());
-                        }
-                        // TODO: Update tree view to show new root directory
-                    }
-
-                    display_markdown(&state_clone.webview, &path);
-                }
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                info!("Selected file: {}", path.display());
+                on_chosen(path);
             }
         }
     });
@@ -0,0 +1,354 @@
+use crate::markdown::renderer::escape_html;
+use comrak::nodes::{Ast, AstNode, LineColumn, NodeHtmlBlock, NodeValue};
+use comrak::Arena;
+use std::cell::RefCell;
+
+/// Prefix of the HTML comments that stand in for `:::` container fences while parsing
+const CONTAINER_MARKER: &str = "<!-- dogmv-container";
+
+/// The kinds of callout, as in GitHub's `> [!NOTE]` alerts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertKind {
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl AlertKind {
+    /// Parses the kind of a GitHub alert (`NOTE`, `TIP`, ...), ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "note" => Some(AlertKind::Note),
+            "tip" => Some(AlertKind::Tip),
+            "important" => Some(AlertKind::Important),
+            "warning" => Some(AlertKind::Warning),
+            "caution" => Some(AlertKind::Caution),
+            _ => None,
+        }
+    }
+
+    /// Parses the kind of a `:::` container, accepting common aliases.
+    ///
+    /// Unknown kinds are shown as notes.
+    fn from_container_name(name: &str) -> Self {
+        Self::from_name(name).unwrap_or(match name.to_lowercase().as_str() {
+            "hint" | "success" => AlertKind::Tip,
+            "attention" => AlertKind::Important,
+            "danger" | "error" => AlertKind::Caution,
+            _ => AlertKind::Note,
+        })
+    }
+
+    fn class_name(self) -> &'static str {
+        match self {
+            AlertKind::Note => "note",
+            AlertKind::Tip => "tip",
+            AlertKind::Important => "important",
+            AlertKind::Warning => "warning",
+            AlertKind::Caution => "caution",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            AlertKind::Note => "Note",
+            AlertKind::Tip => "Tip",
+            AlertKind::Important => "Important",
+            AlertKind::Warning => "Warning",
+            AlertKind::Caution => "Caution",
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            AlertKind::Note => "ℹ️",
+            AlertKind::Tip => "💡",
+            AlertKind::Important => "❗",
+            AlertKind::Warning => "⚠️",
+            AlertKind::Caution => "🛑",
+        }
+    }
+
+    /// Opening markup of a callout; the content follows, then `</div>`.
+    fn opening_html(self, title: &str) -> String {
+        format!(
+            "<div class=\"markdown-alert markdown-alert-{}\">\n<p class=\"markdown-alert-title\"><span class=\"markdown-alert-icon\" aria-hidden=\"true\">{}</span>{}</p>\n",
+            self.class_name(),
+            self.icon(),
+            escape_html(title)
+        )
+    }
+}
+
+/// Turns blockquotes starting with `[!NOTE]`, `[!TIP]`, ... into callouts.
+///
+/// The marker has to be alone on the first line of the quote, as on GitHub.
+/// The quote's content is moved between the generated opening and closing
+/// markup, so the document has to be formatted with `render.unsafe_` enabled.
+pub fn render_alerts<'a>(arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>) {
+    let alerts: Vec<_> = root
+        .descendants()
+        .filter(|node| matches!(node.data.borrow().value, NodeValue::BlockQuote))
+        .filter_map(|node| alert_kind(node).map(|kind| (node, kind)))
+        .collect();
+
+    for (quote, kind) in alerts {
+        remove_alert_marker(quote);
+
+        let start = quote.data.borrow().sourcepos.start;
+        let opening = html_block(arena, kind.opening_html(kind.label()), start);
+        let closing = html_block(arena, "</div>\n".to_string(), start);
+
+        quote.insert_before(opening);
+        let children: Vec<_> = quote.children().collect();
+        for child in children {
+            quote.insert_before(child);
+        }
+        quote.insert_before(closing);
+        quote.detach();
+    }
+}
+
+/// Returns the alert kind if the first line of `quote` is an alert marker.
+fn alert_kind<'a>(quote: &'a AstNode<'a>) -> Option<AlertKind> {
+    let paragraph = quote.first_child()?;
+    if !matches!(paragraph.data.borrow().value, NodeValue::Paragraph) {
+        return None;
+    }
+
+    // The marker may be split over several text nodes ("[", "!NOTE", "]")
+    let mut first_line = String::new();
+    for inline in paragraph.children() {
+        match inline.data.borrow().value {
+            NodeValue::Text(ref text) => first_line.push_str(text),
+            NodeValue::SoftBreak | NodeValue::LineBreak => break,
+            _ => return None,
+        }
+    }
+
+    let name = first_line.trim().strip_prefix("[!")?.strip_suffix(']')?;
+    AlertKind::from_name(name)
+}
+
+/// Removes the first line (the marker) of the alert's first paragraph.
+fn remove_alert_marker<'a>(quote: &'a AstNode<'a>) {
+    let Some(paragraph) = quote.first_child() else {
+        return;
+    };
+    let marker: Vec<_> = paragraph
+        .children()
+        .take_while(|inline| matches!(inline.data.borrow().value, NodeValue::Text(_)))
+        .collect();
+    for inline in marker {
+        inline.detach();
+    }
+    if let Some(line_break) = paragraph.first_child().filter(|inline| {
+        matches!(inline.data.borrow().value, NodeValue::SoftBreak | NodeValue::LineBreak)
+    }) {
+        line_break.detach();
+    }
+    if paragraph.first_child().is_none() {
+        paragraph.detach();
+    }
+}
+
+/// A `::: kind [title]` container block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    kind: AlertKind,
+    title: String,
+}
+
+/// Replaces the fences of `:::` containers with marker comments before parsing.
+///
+/// Fences have to start at the beginning of a line, outside of fenced code,
+/// and every opening fence needs a closing `:::` line. Each marker is a line
+/// of its own, so line numbers stay the same. Returns the rewritten document
+/// and the containers, in the order of their markers.
+pub fn mark_containers(markdown: &str) -> (String, Vec<Container>) {
+    let lines: Vec<&str> = markdown.split_inclusive('\n').collect();
+    let mut replacements: Vec<Option<String>> = vec![None; lines.len()];
+    let mut containers = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+    let mut code_fence: Option<(char, usize)> = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        let content = line.trim_end();
+
+        if let Some((fence_char, fence_length)) = code_fence {
+            let closing = content.trim_start();
+            if closing.chars().take_while(|&c| c == fence_char).count() >= fence_length
+                && closing.trim_start_matches(fence_char).is_empty()
+            {
+                code_fence = None;
+            }
+            continue;
+        }
+        if let Some(fence) = code_fence_start(content) {
+            code_fence = Some(fence);
+            continue;
+        }
+
+        let Some(rest) = content.strip_prefix(":::") else {
+            continue;
+        };
+        let rest = rest.trim_start_matches(':').trim();
+        if rest.is_empty() {
+            // A closing fence; an unmatched one is left alone
+            if let Some(opening) = open.pop() {
+                let index = containers.len();
+                replacements[opening] = Some(format!("{} {} -->\n", CONTAINER_MARKER, index));
+                replacements[i] = Some(format!("{} /{} -->\n", CONTAINER_MARKER, index));
+                let (kind, title) = parse_container_header(lines[opening]);
+                containers.push(Container { kind, title });
+            }
+        } else {
+            open.push(i);
+        }
+    }
+
+    let marked = lines
+        .iter()
+        .zip(replacements)
+        .map(|(line, replacement)| replacement.unwrap_or_else(|| line.to_string()))
+        .collect();
+    (marked, containers)
+}
+
+/// Returns the fence character and length if `line` opens a fenced code block.
+fn code_fence_start(line: &str) -> Option<(char, usize)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let line = &line[indent..];
+    let fence_char = line.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let length = line.chars().take_while(|&c| c == fence_char).count();
+    (length >= 3).then_some((fence_char, length))
+}
+
+/// Parses `::: warning Custom title` into its kind and title.
+fn parse_container_header(line: &str) -> (AlertKind, String) {
+    let header = line.trim().trim_start_matches(':').trim();
+    let (name, title) = header.split_once(char::is_whitespace).unwrap_or((header, ""));
+    let kind = AlertKind::from_container_name(name);
+    let title = match title.trim() {
+        "" => kind.label().to_string(),
+        title => title.to_string(),
+    };
+    (kind, title)
+}
+
+/// The marker nodes of the containers of a parsed document.
+pub struct ContainerNodes<'a> {
+    found: Vec<(&'a AstNode<'a>, &'a AstNode<'a>, Container)>,
+}
+
+impl<'a> ContainerNodes<'a> {
+    /// Finds the marker blocks left by `mark_containers` in the parsed document.
+    ///
+    /// This has to happen before the document's raw HTML is sanitized.
+    pub fn find(root: &'a AstNode<'a>, containers: &[Container]) -> Self {
+        let mut openings = vec![None; containers.len()];
+        let mut found = Vec::new();
+
+        // Marker comments on lines of their own always parse as HTML blocks
+        for node in root.descendants() {
+            let marker = match node.data.borrow().value {
+                NodeValue::HtmlBlock(ref block) => parse_marker(&block.literal),
+                _ => None,
+            };
+            match marker {
+                Some((index, false)) if index < containers.len() => openings[index] = Some(node),
+                Some((index, true)) if index < containers.len() => {
+                    if let Some(opening) = openings[index].take() {
+                        found.push((opening, node, containers[index].clone()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Self { found }
+    }
+
+    /// Replaces the markers with the callout's opening and closing markup.
+    pub fn render(self) {
+        for (opening, closing, container) in self.found {
+            set_html(opening, container.kind.opening_html(&container.title));
+            set_html(closing, "</div>\n".to_string());
+        }
+    }
+}
+
+/// Parses a marker comment into the container index and whether it is the closing one.
+fn parse_marker(literal: &str) -> Option<(usize, bool)> {
+    let marker = literal.trim().strip_prefix(CONTAINER_MARKER)?.strip_suffix("-->")?;
+    let marker = marker.trim();
+    match marker.strip_prefix('/') {
+        Some(index) => index.parse().ok().map(|index| (index, true)),
+        None => marker.parse().ok().map(|index| (index, false)),
+    }
+}
+
+fn set_html<'a>(node: &'a AstNode<'a>, literal: String) {
+    node.data.borrow_mut().value = NodeValue::HtmlBlock(NodeHtmlBlock {
+        block_type: 0,
+        literal,
+    });
+}
+
+fn html_block<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    literal: String,
+    start: LineColumn,
+) -> &'a AstNode<'a> {
+    arena.alloc(AstNode::new(RefCell::new(Ast::new(
+        NodeValue::HtmlBlock(NodeHtmlBlock {
+            block_type: 0,
+            literal,
+        }),
+        start,
+    ))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alert_kind_names() {
+        assert_eq!(AlertKind::from_name("NOTE"), Some(AlertKind::Note));
+        assert_eq!(AlertKind::from_name("Caution"), Some(AlertKind::Caution));
+        assert_eq!(AlertKind::from_name("danger"), None);
+        assert_eq!(AlertKind::from_container_name("danger"), AlertKind::Caution);
+        assert_eq!(AlertKind::from_container_name("custom"), AlertKind::Note);
+    }
+
+    #[test]
+    fn test_mark_containers() {
+        let markdown = "::: warning Mind the gap\nText\n:::\n\n```\n::: note\n```\n:::\n";
+        let (marked, containers) = mark_containers(markdown);
+        assert_eq!(
+            marked,
+            "<!-- dogmv-container 0 -->\nText\n<!-- dogmv-container /0 -->\n\n```\n::: note\n```\n:::\n"
+        );
+        assert_eq!(
+            containers,
+            vec![Container {
+                kind: AlertKind::Warning,
+                title: "Mind the gap".to_string()
+            }]
+        );
+        // Line numbers are preserved
+        assert_eq!(marked.lines().count(), markdown.lines().count());
+    }
+
+    #[test]
+    fn test_unclosed_container_is_left_alone() {
+        let (marked, containers) = mark_containers("::: tip\nNever closed\n");
+        assert_eq!(marked, "::: tip\nNever closed\n");
+        assert!(containers.is_empty());
+    }
+}
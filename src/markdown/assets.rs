@@ -0,0 +1,45 @@
+/// URI scheme under which bundled assets are served to the WebView
+pub const ASSET_SCHEME: &str = "dogmv";
+
+/// The mermaid runtime from `assets/mermaid/mermaid.min.js`, missing in
+/// builds without the vendored file (see build.rs)
+#[cfg(dogmv_mermaid)]
+const MERMAID_JS: Option<&[u8]> = Some(include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/assets/mermaid/mermaid.min.js"
+)));
+
+#[cfg(not(dogmv_mermaid))]
+const MERMAID_JS: Option<&[u8]> = None;
+
+/// Looks up a bundled asset by its `dogmv://assets/...` URI.
+///
+/// Returns the content and its MIME type, or `None` for unknown assets and
+/// assets that were not bundled with this build.
+pub fn asset_for_uri(uri: &str) -> Option<(&'static [u8], &'static str)> {
+    let path = uri.strip_prefix(ASSET_SCHEME)?.strip_prefix("://assets/")?;
+    match path {
+        "mermaid.min.js" => MERMAID_JS.map(|js| (js, "text/javascript")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asset_for_uri() {
+        assert_eq!(asset_for_uri("dogmv://assets/unknown.js"), None);
+        assert_eq!(asset_for_uri("file:///assets/mermaid.min.js"), None);
+    }
+
+    #[cfg(dogmv_mermaid)]
+    #[test]
+    fn test_mermaid_runtime_is_bundled() {
+        let (js, mime) = asset_for_uri("dogmv://assets/mermaid.min.js")
+            .expect("the mermaid runtime is embedded (run assets/mermaid/fetch.sh)");
+        assert_eq!(mime, "text/javascript");
+        assert!(String::from_utf8_lossy(js).contains("mermaid"));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::error::Result;
+use crate::markdown::outline::Heading;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Identifies one version of a file on disk, rendered with one theme.
+///
+/// Two keys are equal only if they refer to the same canonical path with the
+/// same modification time and size, so an edited file never hits a stale entry.
+/// The theme is part of the key because highlighted code depends on it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DocumentKey {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    len: u64,
+    theme: String,
+}
+
+impl DocumentKey {
+    /// Builds the key for the current version of the file at `path`,
+    /// rendered with the theme named `theme`.
+    pub fn for_file(path: &Path, theme: &str) -> Result<Self> {
+        let path = fs::canonicalize(path)?;
+        let metadata = fs::metadata(&path)?;
+        Ok(Self {
+            path,
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            theme: theme.to_string(),
+        })
+    }
+}
+
+/// A rendered HTML body together with the source it was rendered from.
+///
+/// Keeping the source allows the document to be rendered again, e.g. with a
+/// different theme, without reading the file a second time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedDocument {
+    pub source: String,
+    pub html: String,
+    /// Headings for the outline panel
+    pub headings: Vec<Heading>,
+}
+
+/// Bounded least-recently-used cache of rendered documents.
+///
+/// Switching back and forth between recently viewed files is served from
+/// here instead of reading and rendering them again.
+pub struct RenderCache {
+    capacity: usize,
+    // Most recently used entry first
+    entries: VecDeque<(DocumentKey, RenderedDocument)>,
+}
+
+impl RenderCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the cached document for `key` and marks it as recently used.
+    pub fn get(&mut self, key: &DocumentKey) -> Option<RenderedDocument> {
+        let index = self.entries.iter().position(|(k, _)| k == key)?;
+        let entry = self.entries.remove(index)?;
+        let document = entry.1.clone();
+        self.entries.push_front(entry);
+        Some(document)
+    }
+
+    /// Stores the document for `key`, evicting the least recently used entry if full.
+    pub fn insert(&mut self, key: DocumentKey, document: RenderedDocument) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        // Older versions of the same file can never be hit again
+        self.entries.retain(|(k, _)| k.path != key.path);
+        self.entries.push_front((key, document));
+        self.entries.truncate(self.capacity);
+    }
+
+    /// Drops every cached version of the file at `path`.
+    pub fn invalidate(&mut self, path: &Path) {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.entries.retain(|(k, _)| k.path != path);
+    }
+
+    /// Drops all entries, e.g. after rendering settings changed.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> DocumentKey {
+        DocumentKey {
+            path: PathBuf::from(name),
+            modified: None,
+            len: 0,
+            theme: "Light".to_string(),
+        }
+    }
+
+    fn document(html: &str) -> RenderedDocument {
+        RenderedDocument {
+            source: String::new(),
+            html: html.to_string(),
+            headings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_least_recently_used_entry_is_evicted() {
+        let mut cache = RenderCache::new(2);
+        cache.insert(key("/a.md"), document("a"));
+        cache.insert(key("/b.md"), document("b"));
+
+        // Touch a.md so that b.md becomes the oldest entry
+        assert_eq!(cache.get(&key("/a.md")), Some(document("a")));
+        cache.insert(key("/c.md"), document("c"));
+
+        assert_eq!(cache.get(&key("/b.md")), None);
+        assert_eq!(cache.get(&key("/a.md")), Some(document("a")));
+        assert_eq!(cache.get(&key("/c.md")), Some(document("c")));
+    }
+
+    #[test]
+    fn test_modified_file_misses() {
+        let path = std::env::temp_dir().join(format!("dogmv-cache-test-{}.md", std::process::id()));
+        fs::write(&path, "# One").unwrap();
+
+        let mut cache = RenderCache::new(4);
+        let first = DocumentKey::for_file(&path, "Light").unwrap();
+        cache.insert(first.clone(), document("<h1>One</h1>"));
+        assert!(cache.get(&first).is_some());
+
+        fs::write(&path, "# Two, longer").unwrap();
+        let second = DocumentKey::for_file(&path, "Light").unwrap();
+        assert_ne!(first, second);
+        assert!(cache.get(&second).is_none());
+
+        // A render with another theme is a different entry
+        let dark = DocumentKey::for_file(&path, "Dark").unwrap();
+        assert_ne!(second, dark);
+        cache.insert(second.clone(), document("<h1>Two, longer</h1>"));
+        assert!(cache.get(&dark).is_none());
+
+        cache.invalidate(&path);
+        assert!(cache.get(&first).is_none());
+        assert!(cache.get(&second).is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,461 @@
+use crate::markdown::renderer::escape_html;
+use comrak::nodes::{Ast, AstNode, NodeHtmlBlock, NodeValue};
+use comrak::Arena;
+use log::{info, warn};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of rendered diagrams kept before the cache is emptied
+const DIAGRAM_CACHE_CAPACITY: usize = 128;
+
+/// How long a diagram command may run before it is killed
+const DIAGRAM_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How often a running diagram command is checked for exit, timeout and cancellation
+const DIAGRAM_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Commands that render diagram fences to SVG.
+///
+/// A command may include arguments (e.g. `java -jar /opt/plantuml.jar`); the
+/// options selecting SVG output are appended.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiagramTools {
+    /// Graphviz command for ```dot and ```graphviz fences
+    pub dot: String,
+    /// PlantUML command for ```plantuml and ```puml fences
+    pub plantuml: String,
+}
+
+impl Default for DiagramTools {
+    fn default() -> Self {
+        Self {
+            dot: "dot".to_string(),
+            plantuml: "plantuml".to_string(),
+        }
+    }
+}
+
+impl DiagramTools {
+    /// Returns the command line for a fence language, if it is a diagram language.
+    fn command_for(&self, language: &str) -> Option<(&str, &'static [&'static str])> {
+        match language {
+            "dot" | "graphviz" => Some((&self.dot, &["-Tsvg"])),
+            "plantuml" | "puml" => Some((&self.plantuml, &["-tsvg", "-pipe"])),
+            _ => None,
+        }
+    }
+}
+
+static DIAGRAM_TOOLS: OnceLock<DiagramTools> = OnceLock::new();
+
+thread_local! {
+    // Cancellation flag of the render running on this thread, see `with_cancel_token`
+    static CANCEL_TOKEN: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+// SVG by hash of command and source
+static DIAGRAM_CACHE: OnceLock<Mutex<HashMap<u64, String>>> = OnceLock::new();
+
+/// Sets the diagram commands used by all renders.
+///
+/// Must be called before the first render; later calls are ignored.
+pub fn configure(tools: DiagramTools) {
+    if DIAGRAM_TOOLS.set(tools).is_err() {
+        warn!("Diagram tools are already configured, ignoring");
+    }
+}
+
+/// Runs `f`, killing diagram commands it starts as soon as `cancelled` is set.
+///
+/// Renders run on worker threads that are abandoned when the user moves on;
+/// without this a slow diagram tool would keep running to the end.
+pub fn with_cancel_token<T>(cancelled: &Arc<AtomicBool>, f: impl FnOnce() -> T) -> T {
+    let previous = CANCEL_TOKEN.with(|token| token.replace(Some(Arc::clone(cancelled))));
+    let result = f();
+    CANCEL_TOKEN.with(|token| *token.borrow_mut() = previous);
+    result
+}
+
+fn is_cancelled() -> bool {
+    CANCEL_TOKEN.with(|token| {
+        token
+            .borrow()
+            .as_ref()
+            .is_some_and(|cancelled| cancelled.load(Ordering::Relaxed))
+    })
+}
+
+/// Replaces every ```mermaid fence below `root` with a diagram placeholder.
+///
+/// The placeholder keeps the diagram source; `mermaid.js` renders it in the
+/// page with the mermaid runtime served by dogmv. The document has to be
+/// formatted with `render.unsafe_` enabled.
+pub fn render_mermaid_blocks<'a>(root: &'a AstNode<'a>) {
+    for node in root.descendants() {
+        let placeholder = match node.data.borrow().value {
+            NodeValue::CodeBlock(ref block) if fence_language(&block.info) == "mermaid" => {
+                Some(mermaid_placeholder(&block.literal))
+            }
+            _ => None,
+        };
+        if let Some(literal) = placeholder {
+            node.data.borrow_mut().value = NodeValue::HtmlBlock(NodeHtmlBlock {
+                block_type: 0,
+                literal,
+            });
+        }
+    }
+}
+
+/// Replaces dot/graphviz/plantuml fences below `root` with SVG from the local tools.
+///
+/// Results are cached by content, so unchanged diagrams are not rendered again
+/// on reload. If a tool is missing or rejects the diagram, the fence stays a
+/// highlighted code block and a note explaining why is inserted above it.
+pub fn render_external_diagrams<'a>(arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>) {
+    let tools = DIAGRAM_TOOLS.get_or_init(DiagramTools::default);
+    render_external_diagrams_with(arena, root, tools);
+}
+
+fn render_external_diagrams_with<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    tools: &DiagramTools,
+) {
+    // Collect first; nodes are inserted while handling them
+    let diagrams: Vec<_> = root
+        .descendants()
+        .filter_map(|node| match node.data.borrow().value {
+            NodeValue::CodeBlock(ref block) => {
+                let (command, args) = tools.command_for(&fence_language(&block.info))?;
+                Some((node, command.to_string(), args, block.literal.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+
+    for (node, command, args, source) in diagrams {
+        match render_svg(&command, args, &source) {
+            Ok(svg) => {
+                node.data.borrow_mut().value = NodeValue::HtmlBlock(NodeHtmlBlock {
+                    block_type: 0,
+                    literal: format!("<div class=\"diagram\">{}</div>\n", svg_image(&svg)),
+                });
+            }
+            Err(note) => {
+                let start = node.data.borrow().sourcepos.start;
+                let literal = format!("<div class=\"diagram-note\">{}</div>\n", escape_html(&note));
+                let note_node = arena.alloc(AstNode::new(RefCell::new(Ast::new(
+                    NodeValue::HtmlBlock(NodeHtmlBlock {
+                        block_type: 0,
+                        literal,
+                    }),
+                    start,
+                ))));
+                node.insert_before(note_node);
+            }
+        }
+    }
+}
+
+/// Runs `command` on `source` and returns the SVG, or a note for the reader.
+fn render_svg(command: &str, args: &[&str], source: &str) -> std::result::Result<String, String> {
+    let mut hasher = DefaultHasher::new();
+    (command, args, source).hash(&mut hasher);
+    let key = hasher.finish();
+
+    let cache = DIAGRAM_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(svg) = cache.lock().ok().and_then(|cache| cache.get(&key).cloned()) {
+        return Ok(svg);
+    }
+
+    let mut words = command.split_whitespace();
+    let program = words.next().ok_or_else(|| "No diagram command is configured".to_string())?;
+
+    info!("Rendering diagram with {}", command);
+    let args = words.chain(args.iter().copied());
+    let output = run_command(program, args, source, DIAGRAM_TIMEOUT).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => format!(
+            "Diagram not rendered: `{}` was not found. Install it or configure the command in config.toml.",
+            program
+        ),
+        io::ErrorKind::TimedOut => format!(
+            "Diagram not rendered: `{}` did not finish within {} seconds.",
+            program,
+            DIAGRAM_TIMEOUT.as_secs()
+        ),
+        _ => format!("Diagram not rendered: failed to run `{}`: {}", command, e),
+    })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        warn!("{} failed: {}", command, stderr.trim());
+        return Err(format!(
+            "Diagram not rendered: `{}` reported an error:\n{}",
+            program,
+            stderr.trim()
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Drop the XML declaration and doctype in front of the <svg> element
+    let svg = match stdout.find("<svg") {
+        Some(start) => stdout[start..].trim_end().to_string(),
+        None => return Err(format!("Diagram not rendered: `{}` returned no SVG", program)),
+    };
+
+    if let Ok(mut cache) = cache.lock() {
+        if cache.len() >= DIAGRAM_CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(key, svg.clone());
+    }
+    Ok(svg)
+}
+
+/// Runs `program` with `input` on stdin and collects its output.
+///
+/// The process is killed if it runs longer than `timeout` (failing with
+/// `TimedOut`) or the render gets cancelled (failing with `Interrupted`).
+fn run_command<'s, I>(program: &str, args: I, input: &str, timeout: Duration) -> io::Result<Output>
+where
+    I: Iterator<Item = &'s str>,
+{
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Feed stdin and drain stdout/stderr on other threads, so a large output
+    // can't dead-lock the pipes while this thread watches the clock
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        let error = if is_cancelled() {
+            io::Error::new(io::ErrorKind::Interrupted, "the render was cancelled")
+        } else if Instant::now() >= deadline {
+            io::Error::new(io::ErrorKind::TimedOut, "the command timed out")
+        } else {
+            thread::sleep(DIAGRAM_POLL_INTERVAL);
+            continue;
+        };
+        warn!("Killing {}: {}", program, error);
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(error);
+    };
+
+    if let Ok(Err(e)) = writer.join() {
+        // The tool may exit without reading everything, e.g. on a syntax error
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            return Err(e);
+        }
+    }
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Exit status and output of a diagram command
+struct Output {
+    status: ExitStatus,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+/// Returns an `<img>` element showing `svg`.
+///
+/// Tool output is not inlined into the page: as an image, scripts, event
+/// handlers and links in it are inert, and ids of several diagrams can't clash.
+fn svg_image(svg: &str) -> String {
+    format!(
+        "<img src=\"data:image/svg+xml;base64,{}\" alt=\"diagram\">",
+        base64(svg.as_bytes())
+    )
+}
+
+/// Encodes `bytes` as standard, padded base64.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let triple = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |triple, (i, &byte)| triple | (u32::from(byte) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(ALPHABET[((triple >> (18 - 6 * i)) & 0x3f) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Returns the language of a fence info string (its first word, lowercased).
+pub fn fence_language(info: &str) -> String {
+    info.split_whitespace()
+        .next()
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+fn mermaid_placeholder(source: &str) -> String {
+    format!(
+        "<div class=\"mermaid-diagram\"><pre class=\"mermaid-source\">{}</pre><div class=\"mermaid-output\"></div></div>\n",
+        escape_html(source)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fence_language() {
+        assert_eq!(fence_language("mermaid"), "mermaid");
+        assert_eq!(fence_language(" Mermaid title=x"), "mermaid");
+        assert_eq!(fence_language(""), "");
+    }
+
+    fn render(markdown: &str, tools: &DiagramTools) -> String {
+        let arena = Arena::new();
+        let options = comrak::Options::default();
+        let root = comrak::parse_document(&arena, markdown, &options);
+        render_external_diagrams_with(&arena, root, tools);
+
+        let mut options = options;
+        options.render.unsafe_ = true;
+        let mut html = Vec::new();
+        comrak::format_html(root, &options, &mut html).unwrap();
+        String::from_utf8(html).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_diagram_rendered_through_command_and_cached() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("dogmv-diagrams-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tool = dir.join("fake-dot");
+        std::fs::write(
+            &tool,
+            "#!/bin/sh\ncat > /dev/null\necho '<?xml version=\"1.0\"?>'\necho '<svg id=\"fake\"></svg>'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let tools = DiagramTools {
+            dot: tool.display().to_string(),
+            ..DiagramTools::default()
+        };
+        let markdown = "```dot\ndigraph { a -> b }\n```\n";
+        let html = render(markdown, &tools);
+        // Embedded without the XML declaration in front of the <svg> element
+        let image = svg_image("<svg id=\"fake\"></svg>");
+        assert!(html.contains(&format!("<div class=\"diagram\">{}</div>", image)));
+
+        // The second render is served from the cache
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(render(markdown, &tools), html);
+    }
+
+    #[test]
+    fn test_missing_tool_keeps_source_with_note() {
+        let tools = DiagramTools {
+            dot: "dogmv-no-such-dot-command".to_string(),
+            ..DiagramTools::default()
+        };
+        let html = render("```graphviz\ndigraph { a -> b }\n```\n", &tools);
+        assert!(html.contains("<div class=\"diagram-note\">"));
+        assert!(html.contains("`dogmv-no-such-dot-command` was not found"));
+        // The source is still shown as a code block below the note
+        assert!(html.contains("<code class=\"language-graphviz\">"));
+        assert!(html.find("diagram-note").unwrap() < html.find("<pre>").unwrap());
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"<svg>\xff"), "PHN2Zz7/");
+    }
+
+    #[test]
+    fn test_svg_is_embedded_as_an_image() {
+        let svg = "<svg xmlns=\"http://www.w3.org/2000/svg\"><script>alert(1)</script>\
+                   <a xlink:href=\"javascript:alert(2)\"><text onclick=\"alert(3)\">a</text></a></svg>";
+        let image = svg_image(svg);
+        assert_eq!(
+            image,
+            format!("<img src=\"data:image/svg+xml;base64,{}\" alt=\"diagram\">", base64(svg.as_bytes()))
+        );
+        assert!(!image.contains("<svg") && !image.contains("script") && !image.contains("onclick"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_slow_command_is_killed() {
+        let started = Instant::now();
+        let error = run_command("sleep", ["10"].into_iter(), "", Duration::from_millis(200))
+            .err()
+            .expect("the command times out");
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // A cancelled render kills its commands right away
+        let cancelled = Arc::new(AtomicBool::new(true));
+        let error = with_cancel_token(&cancelled, || {
+            run_command("sleep", ["10"].into_iter(), "", DIAGRAM_TIMEOUT)
+        })
+        .err()
+        .expect("the command is cancelled");
+        assert_eq!(error.kind(), io::ErrorKind::Interrupted);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!is_cancelled());
+    }
+
+    #[test]
+    fn test_mermaid_placeholder_escapes_source() {
+        let html = mermaid_placeholder("graph TD\n  A-->B & C\n");
+        assert!(html.starts_with("<div class=\"mermaid-diagram\">"));
+        assert!(html.contains("A--&gt;B &amp; C"));
+    }
+}
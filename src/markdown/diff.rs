@@ -0,0 +1,625 @@
+use crate::markdown::options::MarkdownOptions;
+use crate::markdown::outline::{Heading, Outline};
+use crate::markdown::renderer::{
+    format_document, is_markdown_file, parse_markdown, render_document,
+};
+use crate::markdown::theme::Theme;
+use comrak::nodes::{Ast, AstNode, LineColumn, NodeHtmlBlock, NodeValue};
+use comrak::{Arena, Options};
+use std::cell::RefCell;
+use std::path::Path;
+
+/// Largest table `align` builds for the longest common subsequence (16 MB)
+const MAX_TABLE_CELLS: usize = 4 * 1024 * 1024;
+
+/// How many blocks ahead `align_lookahead` looks for the next match
+const LOOKAHEAD: usize = 64;
+
+/// How a block differs between the compared documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl Change {
+    fn name(self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Changed => "changed",
+        }
+    }
+}
+
+/// The kinds of block that are compared as a whole
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    Paragraph,
+    Heading,
+    CodeBlock,
+    ListItem,
+    TableRow,
+    Other,
+}
+
+impl BlockKind {
+    fn of(value: &NodeValue) -> Option<Self> {
+        match value {
+            NodeValue::Paragraph => Some(BlockKind::Paragraph),
+            NodeValue::Heading(_) => Some(BlockKind::Heading),
+            NodeValue::CodeBlock(_) => Some(BlockKind::CodeBlock),
+            NodeValue::Item(_) | NodeValue::TaskItem(..) => Some(BlockKind::ListItem),
+            NodeValue::TableRow(_) => Some(BlockKind::TableRow),
+            NodeValue::ThematicBreak => Some(BlockKind::Other),
+            _ => None,
+        }
+    }
+
+    /// List items and table rows live inside a list or table of their own kind
+    fn is_nested(self) -> bool {
+        matches!(self, BlockKind::ListItem | BlockKind::TableRow)
+    }
+}
+
+/// A block of one of the documents and the text it is compared by
+struct Block<'a> {
+    node: &'a AstNode<'a>,
+    kind: BlockKind,
+    text: String,
+}
+
+impl<'a> Block<'a> {
+    fn same(&self, other: &Block<'a>) -> bool {
+        self.kind == other.kind && self.text == other.text
+    }
+}
+
+/// One step of the alignment of the old blocks with the new ones, by index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+    Changed(usize, usize),
+}
+
+/// Renders the Markdown document `new` with the blocks that differ from
+/// `old` highlighted.
+///
+/// Paragraphs, headings, code blocks, list items and table rows are compared
+/// as a whole. Added and changed blocks are marked where they are in `new`;
+/// removed blocks are taken over from `old` and shown where they used to be.
+/// A summary of the changes heads the page. Returns the HTML body and the
+/// headings of the merged document.
+pub fn render_diff(old: &str, new: &str, theme: &Theme) -> (String, Vec<Heading>) {
+    render_diff_with(old, new, theme, MarkdownOptions::global())
+}
+
+/// Like `render_document`, showing the differences to `old` for Markdown files.
+///
+/// Other files are rendered as they are in `new`.
+pub fn render_diff_document(
+    old: &str,
+    new: &str,
+    path: &Path,
+    theme: &Theme,
+) -> (String, Vec<Heading>) {
+    if is_markdown_file(path) {
+        render_diff(old, new, theme)
+    } else {
+        render_document(new, path, theme)
+    }
+}
+
+fn render_diff_with(
+    old: &str,
+    new: &str,
+    theme: &Theme,
+    settings: &MarkdownOptions,
+) -> (String, Vec<Heading>) {
+    // Both documents share the arena, so removed blocks can move across
+    let arena = Arena::new();
+    let old = parse_markdown(&arena, old, settings, &mut Options::default());
+    let mut options = Options::default();
+    let mut new = parse_markdown(&arena, new, settings, &mut options);
+
+    let old_blocks = collect_blocks(old.root);
+    let new_blocks = collect_blocks(new.root);
+    let steps = align(old_blocks.len(), new_blocks.len(), |o, n| {
+        old_blocks[o].same(&new_blocks[n])
+    });
+    let steps = pair_changes(steps, |o, n| old_blocks[o].kind == new_blocks[n].kind);
+
+    let changes = merge(&arena, new.root, &old_blocks, &new_blocks, &steps);
+    // Removed headings are listed in the outline too
+    new.outline = Outline::collect(new.root);
+    for &(node, kind, change) in &changes {
+        mark(&arena, node, kind, change);
+    }
+    new.root.prepend(html_block(
+        &arena,
+        summary_html(&changes),
+        LineColumn { line: 1, column: 1 },
+    ));
+
+    let (root, headings) = new.finish(&arena, &mut options);
+    (format_document(root, &options, theme), headings)
+}
+
+/// Collects the blocks compared below `root` in document order.
+fn collect_blocks<'a>(root: &'a AstNode<'a>) -> Vec<Block<'a>> {
+    let mut blocks = Vec::new();
+    collect_into(root, &mut blocks);
+    blocks
+}
+
+fn collect_into<'a>(node: &'a AstNode<'a>, blocks: &mut Vec<Block<'a>>) {
+    for child in node.children() {
+        let kind = BlockKind::of(&child.data.borrow().value);
+        match kind {
+            Some(kind) => {
+                blocks.push(Block {
+                    node: child,
+                    kind,
+                    text: block_text(child),
+                });
+                // Lists nested in an item are compared item by item
+                if kind == BlockKind::ListItem {
+                    for list in child.children().filter(|grandchild| is_list(grandchild)) {
+                        collect_into(list, blocks);
+                    }
+                }
+            }
+            // Containers such as block quotes and lists; raw HTML isn't compared
+            None => collect_into(child, blocks),
+        }
+    }
+}
+
+fn is_list<'a>(node: &'a AstNode<'a>) -> bool {
+    matches!(node.data.borrow().value, NodeValue::List(_))
+}
+
+/// The text a block is compared by, without the lists nested in it.
+fn block_text<'a>(node: &'a AstNode<'a>) -> String {
+    let mut text = String::new();
+    push_text(node, &mut text);
+    text
+}
+
+fn push_text<'a>(node: &'a AstNode<'a>, text: &mut String) {
+    match node.data.borrow().value {
+        NodeValue::Text(ref literal) => text.push_str(literal),
+        NodeValue::HtmlInline(ref literal) => text.push_str(literal),
+        NodeValue::Code(ref code) => text.push_str(&code.literal),
+        NodeValue::Math(ref math) => text.push_str(&math.literal),
+        NodeValue::CodeBlock(ref block) => {
+            text.push_str(&block.info);
+            text.push('\n');
+            text.push_str(&block.literal);
+        }
+        NodeValue::Link(ref link) | NodeValue::Image(ref link) => {
+            text.push_str(&link.url);
+            text.push(' ');
+        }
+        NodeValue::SoftBreak | NodeValue::LineBreak => text.push(' '),
+        NodeValue::TableCell => text.push('|'),
+        // Checking a task off is a change
+        ref value @ NodeValue::TaskItem(..) => text.push_str(&format!("{:?} ", value)),
+        _ => {}
+    }
+    for child in node.children().filter(|child| !is_list(child)) {
+        push_text(child, text);
+    }
+}
+
+/// Aligns `old_len` blocks with `new_len` blocks along their longest common
+/// subsequence, `same(old, new)` telling whether two blocks are equal.
+///
+/// When the changed part is too large for the table of the longest common
+/// subsequence, blocks are matched by looking a few blocks ahead instead.
+fn align(old_len: usize, new_len: usize, same: impl Fn(usize, usize) -> bool) -> Vec<Step> {
+    // Documents are mostly unchanged; only the middle part needs the table
+    let prefix = (0..old_len.min(new_len))
+        .take_while(|&i| same(i, i))
+        .count();
+    let suffix = (0..old_len.min(new_len) - prefix)
+        .take_while(|&i| same(old_len - 1 - i, new_len - 1 - i))
+        .count();
+    let (old_end, new_end) = (old_len - suffix, new_len - suffix);
+
+    let mut steps: Vec<Step> = (0..prefix).map(|i| Step::Same(i, i)).collect();
+    let cells = (old_end - prefix + 1).saturating_mul(new_end - prefix + 1);
+    if cells <= MAX_TABLE_CELLS {
+        align_common(&mut steps, prefix, old_end, new_end, &same);
+    } else {
+        align_lookahead(&mut steps, prefix, old_end, new_end, &same);
+    }
+    steps.extend((0..suffix).map(|k| Step::Same(old_end + k, new_end + k)));
+    steps
+}
+
+/// Aligns old blocks `start..old_end` with new blocks `start..new_end` along
+/// their longest common subsequence.
+fn align_common(
+    steps: &mut Vec<Step>,
+    start: usize,
+    old_end: usize,
+    new_end: usize,
+    same: &impl Fn(usize, usize) -> bool,
+) {
+    // lengths[i][j]: length of the common subsequence of old[i..] and new[j..]
+    let width = new_end - start + 1;
+    let mut lengths = vec![0u32; (old_end - start + 1) * width];
+    for i in (start..old_end).rev() {
+        for j in (start..new_end).rev() {
+            let (row, column) = (i - start, j - start);
+            lengths[row * width + column] = if same(i, j) {
+                lengths[(row + 1) * width + column + 1] + 1
+            } else {
+                lengths[(row + 1) * width + column].max(lengths[row * width + column + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (start, start);
+    while i < old_end && j < new_end {
+        let (row, column) = (i - start, j - start);
+        if same(i, j) {
+            steps.push(Step::Same(i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[(row + 1) * width + column] >= lengths[row * width + column + 1] {
+            steps.push(Step::Removed(i));
+            i += 1;
+        } else {
+            steps.push(Step::Added(j));
+            j += 1;
+        }
+    }
+    steps.extend((i..old_end).map(Step::Removed));
+    steps.extend((j..new_end).map(Step::Added));
+}
+
+/// Aligns old blocks `start..old_end` with new blocks `start..new_end` in
+/// linear time: where two blocks differ, the nearer of the next match of
+/// either within `LOOKAHEAD` blocks decides what was added or removed.
+fn align_lookahead(
+    steps: &mut Vec<Step>,
+    start: usize,
+    old_end: usize,
+    new_end: usize,
+    same: &impl Fn(usize, usize) -> bool,
+) {
+    let (mut i, mut j) = (start, start);
+    while i < old_end && j < new_end {
+        if same(i, j) {
+            steps.push(Step::Same(i, j));
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let added = (j + 1..new_end.min(j + 1 + LOOKAHEAD)).position(|n| same(i, n));
+        let removed = (i + 1..old_end.min(i + 1 + LOOKAHEAD)).position(|o| same(o, j));
+        match (added, removed) {
+            (Some(added), removed) if removed.is_none_or(|removed| added <= removed) => {
+                steps.extend((j..=j + added).map(Step::Added));
+                j += added + 1;
+            }
+            (_, Some(removed)) => {
+                steps.extend((i..=i + removed).map(Step::Removed));
+                i += removed + 1;
+            }
+            _ => {
+                steps.push(Step::Removed(i));
+                steps.push(Step::Added(j));
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    steps.extend((i..old_end).map(Step::Removed));
+    steps.extend((j..new_end).map(Step::Added));
+}
+
+/// Turns a removed and an added block of the same kind between two unchanged
+/// blocks into a changed block.
+fn pair_changes(steps: Vec<Step>, same_kind: impl Fn(usize, usize) -> bool) -> Vec<Step> {
+    let mut paired = Vec::with_capacity(steps.len());
+    let mut run = Vec::new();
+    for step in steps {
+        if let Step::Same(..) = step {
+            paired.extend(pair_run(&run, &same_kind));
+            run.clear();
+            paired.push(step);
+        } else {
+            run.push(step);
+        }
+    }
+    paired.extend(pair_run(&run, &same_kind));
+    paired
+}
+
+fn pair_run(run: &[Step], same_kind: &impl Fn(usize, usize) -> bool) -> Vec<Step> {
+    let added: Vec<usize> = run
+        .iter()
+        .filter_map(|step| match *step {
+            Step::Added(new) => Some(new),
+            _ => None,
+        })
+        .collect();
+    let mut partners: Vec<Option<usize>> = vec![None; added.len()];
+    for step in run {
+        if let Step::Removed(old) = *step {
+            if let Some(k) =
+                (0..added.len()).find(|&k| partners[k].is_none() && same_kind(old, added[k]))
+            {
+                partners[k] = Some(old);
+            }
+        }
+    }
+
+    run.iter()
+        .filter_map(|step| match *step {
+            Step::Removed(old) if partners.contains(&Some(old)) => None,
+            Step::Added(new) => {
+                let k = added.iter().position(|&a| a == new)?;
+                Some(match partners[k] {
+                    Some(old) => Step::Changed(old, new),
+                    None => Step::Added(new),
+                })
+            }
+            step => Some(step),
+        })
+        .collect()
+}
+
+/// Moves the removed blocks of `old` into the new document below `root`.
+///
+/// Returns the blocks to highlight, in document order.
+fn merge<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    old: &[Block<'a>],
+    new: &[Block<'a>],
+    steps: &[Step],
+) -> Vec<(&'a AstNode<'a>, BlockKind, Change)> {
+    let mut changes = Vec::new();
+    let mut previous: Option<(&'a AstNode<'a>, BlockKind)> = None;
+    for (index, step) in steps.iter().enumerate() {
+        let (block, change) = match *step {
+            Step::Same(_, n) => (&new[n], None),
+            Step::Added(n) => (&new[n], Some(Change::Added)),
+            Step::Changed(_, n) => (&new[n], Some(Change::Changed)),
+            Step::Removed(o) => {
+                let next = steps[index + 1..].iter().find_map(|step| match *step {
+                    Step::Same(_, n) | Step::Added(n) | Step::Changed(_, n) => {
+                        Some((new[n].node, new[n].kind))
+                    }
+                    Step::Removed(_) => None,
+                });
+                place_removed(arena, root, &old[o], previous, next);
+                (&old[o], Some(Change::Removed))
+            }
+        };
+        if let Some(change) = change {
+            changes.push((block.node, block.kind, change));
+        }
+        previous = Some((block.node, block.kind));
+    }
+    changes
+}
+
+/// Inserts a removed block after the block before it, or else before the
+/// block after it.
+///
+/// Items and rows join a neighbouring list or table; where there is none they
+/// keep a copy of the list or table they were in.
+fn place_removed<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    block: &Block<'a>,
+    previous: Option<(&'a AstNode<'a>, BlockKind)>,
+    next: Option<(&'a AstNode<'a>, BlockKind)>,
+) {
+    let node = block.node;
+    if block.kind == BlockKind::ListItem {
+        // Nested items are placed on their own
+        let lists: Vec<_> = node.children().filter(|child| is_list(child)).collect();
+        lists.into_iter().for_each(|list| list.detach());
+    }
+    let container = node
+        .parent()
+        .map(|parent| parent.data.borrow().value.clone());
+    node.detach();
+
+    if block.kind.is_nested() {
+        if let Some((previous, _)) = previous.filter(|&(_, kind)| kind == block.kind) {
+            previous.insert_after(node);
+            return;
+        }
+        if let Some((next, _)) = next.filter(|&(_, kind)| kind == block.kind) {
+            next.insert_before(node);
+            return;
+        }
+    }
+
+    let node = match container {
+        Some(value) if block.kind.is_nested() => {
+            let start = node.data.borrow().sourcepos.start;
+            let container: &'a AstNode<'a> =
+                arena.alloc(AstNode::new(RefCell::new(Ast::new(value, start))));
+            container.append(node);
+            container
+        }
+        _ => node,
+    };
+    if let Some((previous, kind)) = previous {
+        outer_block(previous, kind).insert_after(node);
+    } else if let Some((next, kind)) = next {
+        outer_block(next, kind).insert_before(node);
+    } else {
+        root.append(node);
+    }
+}
+
+/// The list or table of an item or row; other blocks themselves
+fn outer_block<'a>(node: &'a AstNode<'a>, kind: BlockKind) -> &'a AstNode<'a> {
+    match node.parent() {
+        Some(parent) if kind.is_nested() => parent,
+        _ => node,
+    }
+}
+
+/// Highlights a block: blocks are wrapped in a `<div>`, items and rows carry
+/// a marker the stylesheet picks them up by.
+fn mark<'a>(arena: &'a Arena<AstNode<'a>>, node: &'a AstNode<'a>, kind: BlockKind, change: Change) {
+    let start = node.data.borrow().sourcepos.start;
+    let marker = format!(
+        "<span class=\"dogmv-diff-marker\" data-diff=\"{}\"></span>",
+        change.name()
+    );
+    match kind {
+        BlockKind::ListItem => node.prepend(html_block(arena, marker + "\n", start)),
+        BlockKind::TableRow => {
+            if let Some(cell) = node.first_child() {
+                let inline = Ast::new(NodeValue::HtmlInline(marker), start);
+                cell.prepend(arena.alloc(AstNode::new(RefCell::new(inline))));
+            }
+        }
+        _ => {
+            let open = format!("<div class=\"dogmv-diff-{}\">\n", change.name());
+            node.insert_before(html_block(arena, open, start));
+            node.insert_after(html_block(arena, "</div>\n".to_string(), start));
+        }
+    }
+}
+
+fn summary_html<'a>(changes: &[(&'a AstNode<'a>, BlockKind, Change)]) -> String {
+    let count = |change| changes.iter().filter(|&&(_, _, c)| c == change).count();
+    let summary = if changes.is_empty() {
+        "No differences".to_string()
+    } else {
+        format!(
+            "{} added, {} removed, {} changed",
+            count(Change::Added),
+            count(Change::Removed),
+            count(Change::Changed)
+        )
+    };
+    format!("<p class=\"dogmv-diff-summary\">{}</p>\n", summary)
+}
+
+fn html_block<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    literal: String,
+    start: LineColumn,
+) -> &'a AstNode<'a> {
+    arena.alloc(AstNode::new(RefCell::new(Ast::new(
+        NodeValue::HtmlBlock(NodeHtmlBlock {
+            block_type: 0,
+            literal,
+        }),
+        start,
+    ))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(old: &[&str], new: &[&str]) -> Vec<Step> {
+        let steps = align(old.len(), new.len(), |o, n| old[o] == new[n]);
+        // Blocks starting with the same letter are of the same kind
+        pair_changes(steps, |o, n| old[o].chars().next() == new[n].chars().next())
+    }
+
+    #[test]
+    fn test_align_and_pair_changes() {
+        assert_eq!(
+            diff(&["a1", "b1", "c1"], &["a1", "b2", "c1", "d1"]),
+            vec![
+                Step::Same(0, 0),
+                Step::Changed(1, 1),
+                Step::Same(2, 2),
+                Step::Added(3)
+            ]
+        );
+        assert_eq!(
+            diff(&["a1", "b1", "c1"], &["a1", "x1", "c1"]),
+            vec![
+                Step::Same(0, 0),
+                Step::Removed(1),
+                Step::Added(1),
+                Step::Same(2, 2)
+            ]
+        );
+        assert_eq!(diff(&[], &["a1"]), vec![Step::Added(0)]);
+        assert_eq!(
+            diff(&["a1", "a2"], &["a2"]),
+            vec![Step::Removed(0), Step::Same(1, 0)]
+        );
+    }
+
+    #[test]
+    fn test_large_documents_are_aligned_without_the_table() {
+        // Changes at both ends leave a middle part too large for the table
+        let old: Vec<usize> = (0..3000).collect();
+        let mut new = vec![10_000];
+        new.extend((0..3000).filter(|&i| i != 1500));
+        new.push(10_001);
+        assert!(old.len() * new.len() > MAX_TABLE_CELLS);
+
+        let steps = align(old.len(), new.len(), |o, n| old[o] == new[n]);
+        let same = steps.iter().filter(|step| matches!(step, Step::Same(..))).count();
+        let removed: Vec<_> = steps.iter().filter(|step| matches!(step, Step::Removed(_))).collect();
+        let added: Vec<_> = steps.iter().filter(|step| matches!(step, Step::Added(_))).collect();
+        assert_eq!(same, 2999);
+        assert_eq!(removed, [&Step::Removed(1500)]);
+        assert_eq!(added, [&Step::Added(0), &Step::Added(3000)]);
+    }
+
+    #[test]
+    fn test_render_diff() {
+        let old = "# Title\n\nKept.\n\nOld paragraph.\n\n- one\n- two\n- three\n\n\
+                   | a | b |\n|---|---|\n| 1 | 2 |\n| 3 | 4 |\n\n```rust\nlet x = 1;\n```\n";
+        let new = "# Title\n\nKept.\n\nNew paragraph.\n\n- one\n- three\n\n\
+                   | a | b |\n|---|---|\n| 1 | 2 |\n| 3 | 5 |\n\n```rust\nlet x = 1;\n```\n\nAdded.\n";
+        let (html, headings) = render_diff(old, new, &Theme::light());
+
+        assert!(html.contains("1 added, 1 removed, 2 changed"));
+        assert!(html.contains("<div class=\"dogmv-diff-changed\">\n<p>New paragraph.</p>\n</div>"));
+        assert!(!html.contains("Old paragraph."));
+        assert!(html.contains("<div class=\"dogmv-diff-added\">\n<p>Added.</p>\n</div>"));
+        assert!(html
+            .contains("<span class=\"dogmv-diff-marker\" data-diff=\"removed\"></span>\ntwo</li>"));
+        assert!(html
+            .contains("<td><span class=\"dogmv-diff-marker\" data-diff=\"changed\"></span>3</td>"));
+        // The removed item stays in its list, between the items around it
+        let one = html.find("one").unwrap();
+        let two = html.find("two").unwrap();
+        let three = html.find("three").unwrap();
+        assert!(one < two && two < three);
+        assert_eq!(html.matches("<ul>").count(), 1);
+        assert_eq!(html.matches("dogmv-diff").count(), 5);
+        assert_eq!(headings.len(), 1);
+    }
+
+    #[test]
+    fn test_removed_item_keeps_its_list() {
+        let (html, _) = render_diff("- only\n", "Text\n", &Theme::light());
+        assert!(html.contains("1 added, 1 removed, 0 changed"));
+        let list = html.find("<ul>").expect("removed item is shown in a list");
+        assert!(list < html.find("Text").unwrap());
+        assert!(html.contains("data-diff=\"removed\""));
+    }
+
+    #[test]
+    fn test_identical_documents() {
+        let (html, _) = render_diff("Same\n", "Same\n", &Theme::light());
+        assert!(html.contains("No differences"));
+        assert!(!html.contains("dogmv-diff-added"));
+    }
+}
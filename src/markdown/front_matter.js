@@ -0,0 +1,15 @@
+// Uses the front matter title of every rendered document as the page title.
+//
+// The title is taken from the metadata card, so it follows live reload
+// patches. dogmv shows the page title in the window title.
+(function () {
+    "use strict";
+
+    function updateTitle() {
+        var card = document.querySelector("body > .front-matter[data-title]");
+        document.title = card ? card.getAttribute("data-title") : "";
+    }
+
+    document.addEventListener("DOMContentLoaded", updateTitle);
+    document.addEventListener("dogmv:patched", updateTitle);
+})();
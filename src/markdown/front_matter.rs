@@ -0,0 +1,294 @@
+use crate::markdown::renderer::escape_html;
+use comrak::nodes::{AstNode, NodeHtmlBlock, NodeValue};
+use log::warn;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Delimiter of TOML front matter; any other delimiter means YAML
+pub const TOML_DELIMITER: &str = "+++";
+
+/// Bytes read from the start of a file when looking for its title
+const TITLE_READ_LIMIT: u64 = 64 * 1024;
+
+/// Metadata from the front matter block at the start of a document.
+///
+/// `---` delimits YAML and `+++` TOML front matter. Fields keep the order
+/// they were written in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrontMatter {
+    pub fields: Vec<(String, FieldValue)>,
+}
+
+/// A front matter value, flattened for display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldValue {
+    Text(String),
+    List(Vec<String>),
+}
+
+impl FrontMatter {
+    /// Parses a front matter block, including its opening and closing delimiter lines.
+    ///
+    /// Returns `None` if `block` is not delimited or is not a YAML/TOML table.
+    pub fn parse(block: &str) -> Option<Self> {
+        let mut lines = block.lines();
+        let delimiter = lines.next()?.trim_end();
+        if delimiter.is_empty() {
+            return None;
+        }
+
+        let mut content = String::new();
+        let mut closed = false;
+        for line in lines {
+            if line.trim_end() == delimiter {
+                closed = true;
+                break;
+            }
+            content.push_str(line);
+            content.push('\n');
+        }
+        if !closed {
+            return None;
+        }
+
+        let fields = if delimiter == TOML_DELIMITER {
+            toml_fields(&content)
+        } else {
+            yaml_fields(&content)
+        };
+        match fields {
+            Ok(fields) => Some(Self { fields }),
+            Err(e) => {
+                warn!("Ignoring invalid front matter: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Returns the value of `key`, if present.
+    pub fn get(&self, key: &str) -> Option<&FieldValue> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns the `title` field, if it is non-empty text.
+    pub fn title(&self) -> Option<&str> {
+        match self.get("title") {
+            Some(FieldValue::Text(title)) if !title.trim().is_empty() => Some(title.trim()),
+            _ => None,
+        }
+    }
+
+    /// Renders the metadata as a header card.
+    ///
+    /// The title is shown as the card's heading and also kept in `data-title`,
+    /// from where the page sets `document.title`.
+    pub fn to_html(&self) -> String {
+        let mut html = String::from("<header class=\"front-matter\"");
+        if let Some(title) = self.title() {
+            html.push_str(&format!(
+                " data-title=\"{0}\">\n<div class=\"front-matter-title\">{0}</div>\n",
+                escape_html(title)
+            ));
+        } else {
+            html.push_str(">\n");
+        }
+
+        let fields: Vec<_> = self.fields.iter().filter(|(name, _)| name != "title").collect();
+        if !fields.is_empty() {
+            html.push_str("<dl class=\"front-matter-fields\">\n");
+            for (name, value) in fields {
+                html.push_str(&format!("<dt>{}</dt><dd>", escape_html(name)));
+                match value {
+                    FieldValue::Text(text) => html.push_str(&escape_html(text)),
+                    FieldValue::List(items) => {
+                        for item in items {
+                            html.push_str(&format!(
+                                "<span class=\"front-matter-tag\">{}</span>",
+                                escape_html(item)
+                            ));
+                        }
+                    }
+                }
+                html.push_str("</dd>\n");
+            }
+            html.push_str("</dl>\n");
+        }
+        html.push_str("</header>\n");
+        html
+    }
+}
+
+/// Parses the front matter of the document below `root`, if it has any.
+pub fn front_matter_of<'a>(root: &'a AstNode<'a>) -> Option<FrontMatter> {
+    // comrak only recognizes front matter at the very start of the document
+    let node = root.first_child()?;
+    let ast = node.data.borrow();
+    match ast.value {
+        NodeValue::FrontMatter(ref block) => FrontMatter::parse(block),
+        _ => None,
+    }
+}
+
+/// Replaces the front matter node below `root` with a card showing `front_matter`.
+///
+/// Front matter that can't be parsed (`None`) is dropped, as comrak does.
+pub fn render_front_matter<'a>(root: &'a AstNode<'a>, front_matter: Option<&FrontMatter>) {
+    let (Some(node), Some(front_matter)) = (root.first_child(), front_matter) else {
+        return;
+    };
+    if matches!(node.data.borrow().value, NodeValue::FrontMatter(_)) {
+        node.data.borrow_mut().value = NodeValue::HtmlBlock(NodeHtmlBlock {
+            block_type: 0,
+            literal: front_matter.to_html(),
+        });
+    }
+}
+
+/// Reads the front matter title of the Markdown file at `path`.
+///
+/// Only the start of the file is read. `delimiter` is the configured YAML
+/// delimiter; TOML front matter is recognized as well.
+pub fn read_title(path: &Path, delimiter: &str) -> Option<String> {
+    let mut head = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(TITLE_READ_LIMIT)
+        .read_to_end(&mut head)
+        .ok()?;
+    let head = String::from_utf8_lossy(&head);
+
+    let first_line = head.lines().next()?.trim_end();
+    if first_line != delimiter && first_line != TOML_DELIMITER {
+        return None;
+    }
+    FrontMatter::parse(&head)?.title().map(str::to_string)
+}
+
+fn yaml_fields(content: &str) -> std::result::Result<Vec<(String, FieldValue)>, String> {
+    use serde_yaml::Value;
+
+    fn text(value: &Value) -> Option<String> {
+        match value {
+            Value::Null => None,
+            Value::Bool(b) => Some(b.to_string()),
+            Value::Number(n) => Some(n.to_string()),
+            Value::String(s) => Some(s.clone()),
+            Value::Tagged(tagged) => text(&tagged.value),
+            other => serde_yaml::to_string(other).ok().map(|s| s.trim().to_string()),
+        }
+    }
+
+    let mapping = match serde_yaml::from_str::<Value>(content).map_err(|e| e.to_string())? {
+        Value::Mapping(mapping) => mapping,
+        // An empty block
+        Value::Null => return Ok(Vec::new()),
+        _ => return Err("front matter is not a mapping".to_string()),
+    };
+
+    Ok(mapping
+        .iter()
+        .filter_map(|(key, value)| {
+            let key = text(key)?;
+            let value = match value {
+                Value::Sequence(items) => FieldValue::List(items.iter().filter_map(text).collect()),
+                value => FieldValue::Text(text(value)?),
+            };
+            Some((key, value))
+        })
+        .collect())
+}
+
+fn toml_fields(content: &str) -> std::result::Result<Vec<(String, FieldValue)>, String> {
+    use toml::Value;
+
+    fn text(value: &Value) -> String {
+        match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        }
+    }
+
+    let table: toml::Table = toml::from_str(content).map_err(|e| e.to_string())?;
+    Ok(table
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Array(items) => FieldValue::List(items.iter().map(text).collect()),
+                value => FieldValue::Text(text(value)),
+            };
+            (key.clone(), value)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_yaml_front_matter() {
+        let front_matter = FrontMatter::parse(
+            "---\ntitle: Design Notes\nauthor: Alice\ndate: 2024-05-01\ntags: [gtk, rust]\ndraft: true\n---\n",
+        )
+        .unwrap();
+        assert_eq!(front_matter.title(), Some("Design Notes"));
+        assert_eq!(
+            front_matter.get("tags"),
+            Some(&FieldValue::List(vec!["gtk".to_string(), "rust".to_string()]))
+        );
+        assert_eq!(front_matter.get("draft"), Some(&FieldValue::Text("true".to_string())));
+        // Fields keep their order
+        let names: Vec<_> = front_matter.fields.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["title", "author", "date", "tags", "draft"]);
+    }
+
+    #[test]
+    fn test_parse_toml_front_matter() {
+        let front_matter = FrontMatter::parse(
+            "+++\ntitle = \"Release Plan\"\nstatus = \"draft\"\ntags = [\"plan\"]\n+++\n",
+        )
+        .unwrap();
+        assert_eq!(front_matter.title(), Some("Release Plan"));
+        assert_eq!(front_matter.get("status"), Some(&FieldValue::Text("draft".to_string())));
+        assert_eq!(front_matter.get("tags"), Some(&FieldValue::List(vec!["plan".to_string()])));
+        let names: Vec<_> = front_matter.fields.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["title", "status", "tags"]);
+    }
+
+    #[test]
+    fn test_invalid_front_matter() {
+        assert_eq!(FrontMatter::parse("---\ntitle: [unclosed\n---\n"), None);
+        assert_eq!(FrontMatter::parse("---\n- just\n- a list\n---\n"), None);
+        // Without a closing delimiter there is no front matter
+        assert_eq!(FrontMatter::parse("---\ntitle: Open\n"), None);
+    }
+
+    #[test]
+    fn test_card_escapes_values() {
+        let front_matter = FrontMatter::parse("---\ntitle: <Notes>\nauthor: A & B\n---\n").unwrap();
+        let html = front_matter.to_html();
+        assert!(html.starts_with("<header class=\"front-matter\" data-title=\"&lt;Notes&gt;\">"));
+        assert!(html.contains("<dt>author</dt><dd>A &amp; B</dd>"));
+        // The title is the heading, not a field
+        assert!(!html.contains("<dt>title</dt>"));
+    }
+
+    #[test]
+    fn test_read_title() {
+        let dir = std::env::temp_dir().join(format!("dogmv-front-matter-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let titled = dir.join("titled.md");
+        std::fs::write(&titled, "---\ntitle: Titled\n---\n\n# Body\n").unwrap();
+        let plain = dir.join("plain.md");
+        std::fs::write(&plain, "# Body\n").unwrap();
+
+        assert_eq!(read_title(&titled, "---"), Some("Titled".to_string()));
+        assert_eq!(read_title(&plain, "---"), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,510 @@
+use crate::error::{DogmvError, Result};
+use crate::markdown::renderer::escape_html;
+use comrak::adapters::SyntaxHighlighterAdapter;
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Color, Theme, ThemeSet};
+use syntect::html::{append_highlighted_html_for_styled_line, IncludeBackground};
+use syntect::parsing::{SyntaxDefinition, SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// Syntect theme used when no other theme is requested
+pub const DEFAULT_THEME: &str = "InspiredGitHub";
+
+/// Shared syntax highlighting state for code blocks and source files.
+///
+/// Loading syntect's syntax and theme dumps is by far the most expensive part
+/// of rendering, so a single engine is created lazily and shared by every
+/// render (see `HighlightEngine::global`). It also carries any syntaxes and
+/// themes loaded by the user on top of syntect's defaults.
+pub struct HighlightEngine {
+    // Shared with the code block highlighters and with engines made by `update`
+    syntax_set: Arc<SyntaxSet>,
+    theme_set: ThemeSet,
+    // Lowercase file names and extensions to syntax names
+    syntax_map: HashMap<String, String>,
+    // Code block highlighters by theme name, built on first use
+    adapters: Mutex<HashMap<String, Arc<CodeBlockHighlighter>>>,
+}
+
+/// The user's additions to syntect's default syntaxes.
+#[derive(Debug, Default, Clone)]
+pub struct SyntaxConfig {
+    /// Directory with extra `.sublime-syntax` files
+    pub syntaxes_dir: Option<PathBuf>,
+    /// Syntax names by file name or extension
+    pub syntax_map: HashMap<String, String>,
+}
+
+static ENGINE: OnceLock<RwLock<Arc<HighlightEngine>>> = OnceLock::new();
+
+static SYNTAX_CONFIG: OnceLock<SyntaxConfig> = OnceLock::new();
+
+impl HighlightEngine {
+    /// Creates an engine with syntect's default syntaxes and themes.
+    pub fn with_defaults() -> Self {
+        info!("Loading syntax highlighting definitions");
+        Self::new(SyntaxSet::load_defaults_newlines(), ThemeSet::load_defaults())
+    }
+
+    /// Creates an engine from the given syntax and theme sets.
+    pub fn new(syntax_set: SyntaxSet, theme_set: ThemeSet) -> Self {
+        Self::with_shared_syntaxes(Arc::new(syntax_set), theme_set)
+    }
+
+    fn with_shared_syntaxes(syntax_set: Arc<SyntaxSet>, theme_set: ThemeSet) -> Self {
+        Self {
+            syntax_set,
+            theme_set,
+            syntax_map: HashMap::new(),
+            adapters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the user syntaxes the shared engine is loaded with.
+    ///
+    /// Must be called before the first render; later calls are ignored.
+    pub fn configure(config: SyntaxConfig) {
+        if SYNTAX_CONFIG.set(config).is_err() {
+            warn!("Syntax configuration is already set, ignoring");
+        }
+    }
+
+    /// Returns the shared engine, loading it on first use.
+    pub fn global() -> Arc<HighlightEngine> {
+        let lock = ENGINE.get_or_init(|| {
+            let mut engine = Self::with_defaults();
+            if let Some(config) = SYNTAX_CONFIG.get() {
+                engine.apply_syntax_config(config);
+            }
+            RwLock::new(Arc::new(engine))
+        });
+        match lock.read() {
+            Ok(engine) => Arc::clone(&engine),
+            Err(poisoned) => Arc::clone(&poisoned.into_inner()),
+        }
+    }
+
+    /// Replaces the shared engine, e.g. after user syntaxes or themes were loaded.
+    ///
+    /// Renders already in progress keep using the engine they started with.
+    pub fn install(engine: HighlightEngine) {
+        let engine = Arc::new(engine);
+        let lock = ENGINE.get_or_init(|| RwLock::new(Arc::clone(&engine)));
+        match lock.write() {
+            Ok(mut current) => *current = engine,
+            Err(poisoned) => *poisoned.into_inner() = engine,
+        }
+    }
+
+    /// Applies `change` to a copy of the shared engine and installs the result.
+    ///
+    /// The shared engine is left untouched if `change` fails.
+    pub fn update<F>(change: F) -> Result<()>
+    where
+        F: FnOnce(&mut HighlightEngine) -> Result<()>,
+    {
+        let current = Self::global();
+        let mut theme_set = ThemeSet::new();
+        theme_set.themes = current.theme_set.themes.clone();
+        let mut engine = Self::with_shared_syntaxes(Arc::clone(&current.syntax_set), theme_set);
+        engine.syntax_map = current.syntax_map.clone();
+        change(&mut engine)?;
+        Self::install(engine);
+        Ok(())
+    }
+
+    /// Loads the user's syntax definitions and file name mapping.
+    ///
+    /// Problems are logged; a broken syntax file doesn't keep the others from loading.
+    pub fn apply_syntax_config(&mut self, config: &SyntaxConfig) {
+        if let Some(dir) = config.syntaxes_dir.as_deref().filter(|dir| dir.is_dir()) {
+            match self.add_syntaxes_from_folder(dir) {
+                Ok(count) => info!("Loaded {} syntax definitions from {}", count, dir.display()),
+                Err(e) => warn!("{}", e),
+            }
+        }
+        self.set_syntax_map(&config.syntax_map);
+    }
+
+    /// Adds every `.sublime-syntax` file found in `dir` to the syntax set.
+    ///
+    /// Files that fail to parse are skipped with a warning. Returns the number
+    /// of syntaxes added.
+    pub fn add_syntaxes_from_folder(&mut self, dir: &Path) -> Result<usize> {
+        let entries = fs::read_dir(dir)
+            .map_err(|e| DogmvError::DirectoryReadError(format!("{}: {}", dir.display(), e)))?;
+
+        let mut paths: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "sublime-syntax"))
+            .collect();
+        paths.sort();
+
+        let mut builder = SyntaxSet::clone(&self.syntax_set).into_builder();
+        let mut count = 0;
+        for path in paths {
+            match load_syntax(&path) {
+                Ok(syntax) => {
+                    info!("Adding syntax '{}' from {}", syntax.name, path.display());
+                    builder.add(syntax);
+                    count += 1;
+                }
+                Err(e) => warn!("Skipping syntax definition: {}", e),
+            }
+        }
+
+        if count > 0 {
+            self.syntax_set = Arc::new(builder.build());
+            self.clear_adapters();
+        }
+        Ok(count)
+    }
+
+    /// Maps file names (e.g. `Justfile`) and extensions (e.g. `nix`) to syntax names.
+    ///
+    /// Fenced code blocks use the same mapping for their info string.
+    pub fn set_syntax_map(&mut self, syntax_map: &HashMap<String, String>) {
+        self.syntax_map = syntax_map
+            .iter()
+            .map(|(key, name)| (key.to_lowercase(), name.clone()))
+            .collect();
+        for name in self.syntax_map.values() {
+            if self.syntax_by_name(name).is_none() {
+                warn!("Syntax map refers to unknown syntax '{}'", name);
+            }
+        }
+        self.clear_adapters();
+    }
+
+    /// Picks the syntax for a source file.
+    ///
+    /// The syntax map is consulted first (file name, then extension), then the
+    /// syntaxes' own file extensions, then the first line (e.g. a shebang).
+    pub fn find_syntax(&self, path: &Path, code: &str) -> &SyntaxReference {
+        self.syntax_for_path(path)
+            .or_else(|| self.syntax_set.find_syntax_by_first_line(code))
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text())
+    }
+
+    /// Picks the syntax for a source file by its name alone.
+    ///
+    /// Returns `None` if neither the syntax map nor any syntax knows the file.
+    pub fn syntax_for_path(&self, path: &Path) -> Option<&SyntaxReference> {
+        let file_name = path.file_name().map(|name| name.to_string_lossy());
+        let extension = path.extension().map(|ext| ext.to_string_lossy());
+
+        let mapped = [&file_name, &extension]
+            .into_iter()
+            .flatten()
+            .filter_map(|key| self.syntax_map.get(&key.to_lowercase()))
+            .find_map(|name| self.syntax_by_name(name));
+
+        mapped
+            .or_else(|| {
+                extension
+                    .as_ref()
+                    .and_then(|ext| self.syntax_set.find_syntax_by_extension(ext))
+            })
+            // Syntaxes list whole file names such as "Makefile" as extensions
+            .or_else(|| {
+                file_name
+                    .as_ref()
+                    .and_then(|name| self.syntax_set.find_syntax_by_extension(name))
+            })
+    }
+
+    /// Finds a syntax by its name, or failing that by extension.
+    fn syntax_by_name(&self, name: &str) -> Option<&SyntaxReference> {
+        self.syntax_set
+            .find_syntax_by_name(name)
+            .or_else(|| self.syntax_set.find_syntax_by_token(name))
+    }
+
+    /// Adds a theme under `name`, replacing any theme with the same name.
+    pub fn add_theme(&mut self, name: &str, theme: Theme) {
+        self.theme_set.themes.insert(name.to_string(), theme);
+        self.clear_adapters();
+    }
+
+    pub fn syntax_set(&self) -> &SyntaxSet {
+        &self.syntax_set
+    }
+
+    /// Returns `true` if a theme called `name` is available.
+    pub fn has_theme(&self, name: &str) -> bool {
+        self.theme_set.themes.contains_key(name)
+    }
+
+    /// Returns the theme called `name`, falling back to the default theme.
+    pub fn theme(&self, name: &str) -> &Theme {
+        self.theme_set
+            .themes
+            .get(name)
+            .or_else(|| self.theme_set.themes.get(DEFAULT_THEME))
+            .or_else(|| self.theme_set.themes.values().next())
+            .expect("syntect ships with default themes")
+    }
+
+    /// Returns `name` if a theme of that name is available, or else the default theme.
+    ///
+    /// Unknown themes are reported here, so call this once when a theme is
+    /// configured or picked rather than on every render.
+    pub fn resolve_theme<'a>(&self, name: &'a str) -> &'a str {
+        if self.has_theme(name) {
+            name
+        } else {
+            warn!("Unknown highlight theme '{}', using {}", name, DEFAULT_THEME);
+            DEFAULT_THEME
+        }
+    }
+
+    /// Returns the comrak code block adapter for the theme called `name`.
+    ///
+    /// An unknown theme silently gets the default theme's adapter; see `resolve_theme`.
+    pub fn adapter(&self, name: &str) -> Arc<CodeBlockHighlighter> {
+        let name = if self.has_theme(name) {
+            name
+        } else {
+            DEFAULT_THEME
+        };
+
+        let mut adapters = match self.adapters.lock() {
+            Ok(adapters) => adapters,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let adapter = adapters.entry(name.to_string()).or_insert_with(|| {
+            info!("Creating code block highlighter for theme: {}", name);
+            Arc::new(CodeBlockHighlighter {
+                syntax_set: Arc::clone(&self.syntax_set),
+                theme: self.theme(name).clone(),
+                syntax_map: self.syntax_map.clone(),
+            })
+        });
+        Arc::clone(adapter)
+    }
+
+    fn clear_adapters(&mut self) {
+        if let Ok(adapters) = self.adapters.get_mut() {
+            adapters.clear();
+        }
+    }
+}
+
+/// Reads one `.sublime-syntax` file.
+fn load_syntax(path: &Path) -> Result<SyntaxDefinition> {
+    let content = fs::read_to_string(path)?;
+    let fallback_name = path.file_stem().and_then(|stem| stem.to_str());
+    SyntaxDefinition::load_from_str(&content, true, fallback_name)
+        .map_err(|e| DogmvError::RenderingError(format!("{}: {}", path.display(), e)))
+}
+
+/// comrak code block adapter that resolves fence languages through the
+/// user's syntax map before highlighting them with syntect.
+///
+/// Works like comrak's `SyntectAdapter`, but shares the engine's syntax set
+/// instead of owning a copy.
+pub struct CodeBlockHighlighter {
+    syntax_set: Arc<SyntaxSet>,
+    theme: Theme,
+    syntax_map: HashMap<String, String>,
+}
+
+impl CodeBlockHighlighter {
+    fn highlight(
+        &self,
+        code: &str,
+        syntax: &SyntaxReference,
+    ) -> std::result::Result<String, syntect::Error> {
+        let mut highlighter = HighlightLines::new(syntax, &self.theme);
+        let background = self.theme.settings.background.unwrap_or(Color::WHITE);
+
+        let mut html = String::new();
+        for line in LinesWithEndings::from(code) {
+            let regions = highlighter.highlight_line(line, &self.syntax_set)?;
+            append_highlighted_html_for_styled_line(
+                &regions,
+                IncludeBackground::IfDifferent(background),
+                &mut html,
+            )?;
+        }
+        Ok(html)
+    }
+}
+
+impl SyntaxHighlighterAdapter for CodeBlockHighlighter {
+    fn write_highlighted(
+        &self,
+        output: &mut dyn Write,
+        lang: Option<&str>,
+        code: &str,
+    ) -> io::Result<()> {
+        let lang = match lang {
+            Some(lang) if !lang.is_empty() => self
+                .syntax_map
+                .get(&lang.to_lowercase())
+                .map(String::as_str)
+                .unwrap_or(lang),
+            _ => "Plain Text",
+        };
+        let syntax = self
+            .syntax_set
+            .find_syntax_by_token(lang)
+            .or_else(|| self.syntax_set.find_syntax_by_first_line(code))
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+
+        match self.highlight(code, syntax) {
+            Ok(html) => output.write_all(html.as_bytes()),
+            Err(e) => {
+                warn!("Failed to highlight code block: {}", e);
+                output.write_all(escape_html(code).as_bytes())
+            }
+        }
+    }
+
+    fn write_pre_tag(
+        &self,
+        output: &mut dyn Write,
+        mut attributes: HashMap<String, String>,
+    ) -> io::Result<()> {
+        let background = self.theme.settings.background.unwrap_or(Color::WHITE);
+        let style = format!(
+            "background-color:#{:02x}{:02x}{:02x};",
+            background.r, background.g, background.b
+        );
+        attributes
+            .entry("style".to_string())
+            .or_default()
+            .insert_str(0, &style);
+        write_opening_tag(output, "pre", attributes)
+    }
+
+    fn write_code_tag(
+        &self,
+        output: &mut dyn Write,
+        attributes: HashMap<String, String>,
+    ) -> io::Result<()> {
+        write_opening_tag(output, "code", attributes)
+    }
+}
+
+/// Writes `<tag name="value" ...>`, with the attributes sorted by name.
+fn write_opening_tag(
+    output: &mut dyn Write,
+    tag: &str,
+    attributes: HashMap<String, String>,
+) -> io::Result<()> {
+    write!(output, "<{}", tag)?;
+    for (name, value) in attributes.into_iter().collect::<BTreeMap<_, _>>() {
+        write!(output, " {}=\"{}\"", name, escape_html(&value))?;
+    }
+    output.write_all(b">")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_global_engine_is_shared() {
+        let first = HighlightEngine::global();
+        let second = HighlightEngine::global();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn test_adapter_is_cached_per_theme() {
+        let engine = HighlightEngine::global();
+        let first = engine.adapter(DEFAULT_THEME);
+        let second = engine.adapter(DEFAULT_THEME);
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // Unknown themes fall back to the default adapter
+        let unknown = engine.adapter("no-such-theme");
+        assert!(Arc::ptr_eq(&first, &unknown));
+        assert_eq!(engine.resolve_theme("no-such-theme"), DEFAULT_THEME);
+        assert_eq!(engine.resolve_theme("base16-ocean.dark"), "base16-ocean.dark");
+    }
+
+    #[test]
+    fn test_adapters_share_the_syntax_set() {
+        let engine = HighlightEngine::with_defaults();
+        let light = engine.adapter(DEFAULT_THEME);
+        let dark = engine.adapter("base16-ocean.dark");
+        assert!(Arc::ptr_eq(&light.syntax_set, &engine.syntax_set));
+        assert!(Arc::ptr_eq(&dark.syntax_set, &engine.syntax_set));
+
+        let mut html = Vec::new();
+        light
+            .write_pre_tag(&mut html, HashMap::from([("lang".to_string(), "rust".to_string())]))
+            .unwrap();
+        light
+            .write_highlighted(&mut html, Some("rust"), "fn main() {}\n")
+            .unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.starts_with("<pre lang=\"rust\" style=\"background-color:#"));
+        assert!(html.contains("<span style="));
+        assert!(html.contains("main"));
+    }
+
+    const JUST_SYNTAX: &str = r#"%YAML 1.2
+---
+name: Just
+file_extensions: [just]
+scope: source.just
+contexts:
+  main:
+    - match: '^[a-z]+:'
+      scope: entity.name.function.just
+"#;
+
+    #[test]
+    fn test_user_syntaxes_and_syntax_map() {
+        let dir = std::env::temp_dir().join(format!("dogmv-syntaxes-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("just.sublime-syntax"), JUST_SYNTAX).unwrap();
+        fs::write(dir.join("broken.sublime-syntax"), "name: [").unwrap();
+
+        let mut syntax_map = HashMap::new();
+        syntax_map.insert("Justfile".to_string(), "Just".to_string());
+        syntax_map.insert("flake.lock".to_string(), "JSON".to_string());
+
+        let mut engine = HighlightEngine::with_defaults();
+        engine.apply_syntax_config(&SyntaxConfig {
+            syntaxes_dir: Some(dir.clone()),
+            syntax_map,
+        });
+
+        // The broken file is skipped, the valid one is added
+        assert_eq!(engine.find_syntax(Path::new("recipes.just"), "").name, "Just");
+        assert_eq!(engine.find_syntax(Path::new("/src/Justfile"), "").name, "Just");
+        assert_eq!(engine.find_syntax(Path::new("flake.lock"), "{}").name, "JSON");
+        // Built-in lookups still work
+        assert_eq!(engine.find_syntax(Path::new("main.rs"), "").name, "Rust");
+        assert_eq!(
+            engine.find_syntax(Path::new("run"), "#!/bin/bash\n").name,
+            "Bourne Again Shell (bash)"
+        );
+
+        // Fenced code blocks resolve their language through the same mapping
+        let highlight = |lang: &str| {
+            let mut html = Vec::new();
+            engine
+                .adapter(DEFAULT_THEME)
+                .write_highlighted(&mut html, Some(lang), "build:\n")
+                .unwrap();
+            String::from_utf8(html).unwrap()
+        };
+        assert_eq!(highlight("justfile"), highlight("just"));
+        assert_ne!(highlight("justfile"), highlight("plain-text-language"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
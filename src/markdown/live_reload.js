@@ -0,0 +1,82 @@
+// Live reload support injected into every rendered document.
+//
+// `window.dogmv.patch(html)` replaces only the top-level blocks of <body> that
+// differ from the previous render. Unchanged blocks keep their DOM nodes, so
+// images are not decoded again and <details>/media state survives a reload.
+(function () {
+    "use strict";
+
+    // Source HTML of each top-level block as last rendered by dogmv.
+    // Compared instead of the live DOM, which the user may have changed
+    // (e.g. by opening a <details> element).
+    var blocks = null;
+
+    function snapshot() {
+        blocks = Array.prototype.map.call(document.body.children, function (el) {
+            return el.outerHTML;
+        });
+    }
+
+    function highlight(el) {
+        el.classList.add("dogmv-changed");
+        setTimeout(function () {
+            el.classList.remove("dogmv-changed");
+        }, 1200);
+    }
+
+    function patch(html) {
+        if (!document.body) {
+            return false;
+        }
+        if (blocks === null) {
+            snapshot();
+        }
+
+        var template = document.createElement("template");
+        template.innerHTML = html;
+        var incoming = Array.prototype.slice.call(template.content.children);
+        var next = incoming.map(function (el) {
+            return el.outerHTML;
+        });
+        var current = Array.prototype.slice.call(document.body.children);
+
+        if (current.length !== blocks.length) {
+            // The page was modified behind our back; ask for a full reload
+            return false;
+        }
+
+        // Keep the common prefix and suffix, replace everything in between
+        var start = 0;
+        while (start < blocks.length && start < next.length && blocks[start] === next[start]) {
+            start++;
+        }
+        var oldEnd = blocks.length;
+        var newEnd = next.length;
+        while (oldEnd > start && newEnd > start && blocks[oldEnd - 1] === next[newEnd - 1]) {
+            oldEnd--;
+            newEnd--;
+        }
+
+        var anchor = oldEnd < current.length ? current[oldEnd] : null;
+        for (var i = start; i < oldEnd; i++) {
+            current[i].remove();
+        }
+
+        var inserted = [];
+        for (var j = start; j < newEnd; j++) {
+            var node = document.importNode(incoming[j], true);
+            document.body.insertBefore(node, anchor);
+            inserted.push(node);
+            highlight(node);
+        }
+
+        blocks = next;
+        document.dispatchEvent(new CustomEvent("dogmv:patched", { detail: { nodes: inserted } }));
+        return true;
+    }
+
+    window.dogmv = window.dogmv || {};
+    window.dogmv.patch = patch;
+
+    document.addEventListener("DOMContentLoaded", snapshot);
+})();
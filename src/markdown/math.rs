@@ -0,0 +1,103 @@
+use crate::markdown::renderer::escape_html;
+use comrak::nodes::{AstNode, NodeValue};
+use log::warn;
+
+/// Replaces every math node (`$...$`, `$$...$$`) below `root` with its rendering.
+///
+/// The rendered formulas are raw HTML nodes, so the document has to be
+/// formatted with `render.unsafe_` enabled.
+pub fn render_math<'a>(root: &'a AstNode<'a>) {
+    for node in root.descendants() {
+        let rendered = match node.data.borrow().value {
+            NodeValue::Math(ref math) => Some(render_formula(&math.literal, math.display_math)),
+            _ => None,
+        };
+        if let Some(html) = rendered {
+            node.data.borrow_mut().value = NodeValue::HtmlInline(html);
+        }
+    }
+}
+
+/// Renders a TeX formula to MathML with the KaTeX engine bundled in the binary.
+///
+/// MathML is drawn by WebKit itself, so neither KaTeX's stylesheet nor its
+/// fonts are needed. A formula KaTeX cannot parse is shown as an error span
+/// holding the source and the parser's message.
+pub fn render_formula(tex: &str, display: bool) -> String {
+    let opts = katex::Opts::builder()
+        .display_mode(display)
+        .output_type(katex::OutputType::Mathml)
+        .throw_on_error(true)
+        .build();
+
+    let result = match opts {
+        Ok(opts) => katex::render_with_opts(tex, &opts),
+        Err(e) => {
+            warn!("Invalid KaTeX options: {}", e);
+            return error_span(tex, "math rendering is unavailable");
+        }
+    };
+
+    match result {
+        Ok(html) => html,
+        Err(e) => {
+            let message = error_message(&e);
+            warn!("Failed to render formula '{}': {}", tex, message);
+            error_span(tex, &message)
+        }
+    }
+}
+
+fn error_span(tex: &str, message: &str) -> String {
+    format!(
+        r#"<span class="math-error" title="{}"><code>{}</code><span class="math-error-message">{}</span></span>"#,
+        escape_html(message),
+        escape_html(tex),
+        escape_html(message)
+    )
+}
+
+/// Extracts KaTeX's own message from the JavaScript exception.
+///
+/// The error detail is the debug form of the thrown JS value, e.g.
+/// `String("ParseError: KaTeX parse error: Expected '}' ...")`.
+fn error_message(error: &katex::Error) -> String {
+    let detail = match error {
+        katex::Error::JsExecError(detail) => detail.as_str(),
+        _ => return error.to_string(),
+    };
+    let message = detail
+        .split_once("KaTeX parse error: ")
+        .map(|(_, message)| message)
+        .unwrap_or(detail);
+    message
+        .trim_end_matches("\")")
+        .replace("\\\\", "\\")
+        .replace("\\\"", "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_inline_and_display_formula() {
+        let inline = render_formula("x^2", false);
+        assert!(inline.contains("<math"));
+        assert!(inline.contains("<msup>"));
+        assert!(!inline.contains("display=\"block\""));
+
+        let display = render_formula("\\int_0^1 x\\,dx", true);
+        assert!(display.contains("display=\"block\""));
+    }
+
+    #[test]
+    fn test_malformed_formula_renders_error_span() {
+        let html = render_formula("\\frac{1}{", false);
+        assert!(html.starts_with("<span class=\"math-error\""));
+        assert!(html.contains("<code>\\frac{1}{</code>"));
+        assert!(html.contains("Unexpected end of input"));
+        // The JS wrapper around the message is stripped
+        assert!(!html.contains("String("));
+    }
+}
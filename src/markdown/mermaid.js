@@ -0,0 +1,95 @@
+// Renders ```mermaid blocks injected into every rendered document.
+//
+// The mermaid runtime is only loaded from dogmv's local asset scheme when the
+// page contains a diagram. Diagrams are rendered into their placeholder, so
+// the top-level blocks of <body> stay as rendered for live reload patching.
+(function () {
+    "use strict";
+
+    var RUNTIME_URL = "dogmv://assets/mermaid.min.js";
+    var runtime = null;
+    var counter = 0;
+
+    function loadRuntime() {
+        if (runtime === null) {
+            runtime = new Promise(function (resolve, reject) {
+                var script = document.createElement("script");
+                script.src = RUNTIME_URL;
+                script.onload = function () {
+                    var dark = document.documentElement.getAttribute("data-dogmv-theme") === "dark";
+                    window.mermaid.initialize({
+                        startOnLoad: false,
+                        theme: dark ? "dark" : "default",
+                    });
+                    resolve(window.mermaid);
+                };
+                script.onerror = function () {
+                    reject(new Error("The mermaid runtime is not bundled with this build of dogmv"));
+                };
+                document.head.appendChild(script);
+            });
+        }
+        return runtime;
+    }
+
+    function showError(container, message) {
+        var output = container.querySelector(".mermaid-output");
+        var error = document.createElement("pre");
+        error.className = "mermaid-error";
+        error.textContent = message;
+        output.replaceChildren(error);
+        container.classList.remove("mermaid-rendered");
+        container.classList.add("mermaid-failed");
+    }
+
+    function renderDiagram(mermaid, container) {
+        var source = container.querySelector(".mermaid-source").textContent;
+        var id = "dogmv-mermaid-" + counter++;
+        mermaid.render(id, source).then(function (result) {
+            container.querySelector(".mermaid-output").innerHTML = result.svg;
+            container.classList.remove("mermaid-failed");
+            container.classList.add("mermaid-rendered");
+        }, function (error) {
+            showError(container, error && error.message ? error.message : String(error));
+            // mermaid leaves its scratch element in <body> when parsing fails
+            var leftover = document.getElementById("d" + id);
+            if (leftover) {
+                leftover.remove();
+            }
+        });
+    }
+
+    function renderAll(nodes) {
+        var diagrams = [];
+        nodes.forEach(function (node) {
+            if (node.classList && node.classList.contains("mermaid-diagram")) {
+                diagrams.push(node);
+            }
+            if (node.querySelectorAll) {
+                diagrams.push.apply(diagrams, node.querySelectorAll(".mermaid-diagram"));
+            }
+        });
+        if (diagrams.length === 0) {
+            return;
+        }
+
+        loadRuntime().then(function (mermaid) {
+            diagrams.forEach(function (container) {
+                renderDiagram(mermaid, container);
+            });
+        }, function (error) {
+            diagrams.forEach(function (container) {
+                showError(container, error.message);
+            });
+        });
+    }
+
+    document.addEventListener("DOMContentLoaded", function () {
+        renderAll([document.body]);
+    });
+
+    // Blocks replaced by a live reload patch are rendered again
+    document.addEventListener("dogmv:patched", function (event) {
+        renderAll(event.detail.nodes);
+    });
+})();
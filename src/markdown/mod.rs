@@ -1,3 +1,27 @@
+pub mod alerts;
+pub mod assets;
+pub mod cache;
+pub mod diagrams;
+pub mod diff;
+pub mod front_matter;
+pub mod highlight;
+pub mod math;
+pub mod options;
+pub mod outline;
 pub mod renderer;
+pub mod theme;
+pub mod toc;
 
-pub use renderer::{create_html, load_markdown, render_markdown, is_markdown_file, render_source_code};
+pub use assets::{asset_for_uri, ASSET_SCHEME};
+pub use cache::{DocumentKey, RenderCache, RenderedDocument};
+pub use diagrams::{with_cancel_token, DiagramTools};
+pub use diff::{render_diff, render_diff_document};
+pub use front_matter::read_title;
+pub use highlight::{HighlightEngine, SyntaxConfig};
+pub use options::MarkdownOptions;
+pub use outline::Heading;
+pub use renderer::{
+    create_html, load_markdown, render_document, render_markdown, is_markdown_file,
+    is_supported_file, render_source_code,
+};
+pub use theme::{available_themes, Theme, ThemeChoice};
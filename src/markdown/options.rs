@@ -0,0 +1,183 @@
+use comrak::Options;
+use log::warn;
+use serde::{Deserialize, Deserializer};
+use std::sync::OnceLock;
+
+/// comrak settings used to render Markdown, read from `[markdown]` in `config.toml`.
+///
+/// The defaults are GitHub Flavored Markdown plus footnotes, heading anchors,
+/// `---` front matter and dollar math.
+///
+/// Every option of comrak 0.24 is mirrored except:
+/// - `render.width` and `render.list_style`: they only affect CommonMark
+///   output, and dogmv renders HTML
+/// - `extension.shortcodes`: needs comrak's `shortcodes` feature, which
+///   dogmv doesn't enable
+///
+/// `ignore_setext`, `ignore_empty_links`, `gfm_quirks` and the other options
+/// of later comrak releases don't exist in 0.24.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarkdownOptions {
+    /// Syntax extensions (`[markdown.extension]`)
+    pub extension: ExtensionSettings,
+    /// Parser behavior (`[markdown.parse]`)
+    pub parse: ParseSettings,
+    /// HTML output (`[markdown.render]`)
+    pub render: RenderSettings,
+}
+
+/// Mirrors comrak's `ExtensionOptions`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExtensionSettings {
+    pub strikethrough: bool,
+    pub tagfilter: bool,
+    pub table: bool,
+    pub autolink: bool,
+    pub tasklist: bool,
+    pub superscript: bool,
+    /// Prefix for heading `id`s; `false` turns the anchors off
+    #[serde(deserialize_with = "header_ids")]
+    pub header_ids: Option<String>,
+    pub footnotes: bool,
+    pub description_lists: bool,
+    /// Front matter delimiter; `false` turns front matter off
+    #[serde(deserialize_with = "front_matter_delimiter")]
+    pub front_matter_delimiter: Option<String>,
+    pub multiline_block_quotes: bool,
+    pub math_dollars: bool,
+    pub math_code: bool,
+    pub wikilinks_title_after_pipe: bool,
+    pub wikilinks_title_before_pipe: bool,
+}
+
+/// Mirrors comrak's `ParseOptions`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParseSettings {
+    pub smart: bool,
+    pub default_info_string: Option<String>,
+    pub relaxed_tasklist_matching: bool,
+    pub relaxed_autolinks: bool,
+}
+
+/// Mirrors comrak's `RenderOptions` for HTML output.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    pub hardbreaks: bool,
+    pub github_pre_lang: bool,
+    pub full_info_string: bool,
+    /// Pass raw HTML written in documents through
+    #[serde(rename = "unsafe")]
+    pub unsafe_: bool,
+    pub escape: bool,
+    pub sourcepos: bool,
+    pub escaped_char_spans: bool,
+}
+
+impl Default for ExtensionSettings {
+    fn default() -> Self {
+        Self {
+            strikethrough: true,
+            tagfilter: true,
+            table: true,
+            autolink: true,
+            tasklist: true,
+            superscript: false,
+            header_ids: Some(String::new()),
+            footnotes: true,
+            description_lists: false,
+            front_matter_delimiter: Some("---".to_string()),
+            multiline_block_quotes: false,
+            math_dollars: true,
+            math_code: false,
+            wikilinks_title_after_pipe: false,
+            wikilinks_title_before_pipe: false,
+        }
+    }
+}
+
+static MARKDOWN_OPTIONS: OnceLock<MarkdownOptions> = OnceLock::new();
+
+impl MarkdownOptions {
+    /// Sets the options used by all renders.
+    ///
+    /// Must be called before the first render; later calls are ignored.
+    pub fn configure(options: MarkdownOptions) {
+        if MARKDOWN_OPTIONS.set(options).is_err() {
+            warn!("Markdown options are already set, ignoring");
+        }
+    }
+
+    /// Returns the configured options, or the defaults.
+    pub fn global() -> &'static MarkdownOptions {
+        MARKDOWN_OPTIONS.get_or_init(MarkdownOptions::default)
+    }
+
+    /// Copies these settings into comrak's options.
+    pub fn apply_to(&self, options: &mut Options) {
+        let extension = &self.extension;
+        options.extension.strikethrough = extension.strikethrough;
+        options.extension.tagfilter = extension.tagfilter;
+        options.extension.table = extension.table;
+        options.extension.autolink = extension.autolink;
+        options.extension.tasklist = extension.tasklist;
+        options.extension.superscript = extension.superscript;
+        options.extension.header_ids = extension.header_ids.clone();
+        options.extension.footnotes = extension.footnotes;
+        options.extension.description_lists = extension.description_lists;
+        options.extension.front_matter_delimiter = extension.front_matter_delimiter.clone();
+        options.extension.multiline_block_quotes = extension.multiline_block_quotes;
+        options.extension.math_dollars = extension.math_dollars;
+        options.extension.math_code = extension.math_code;
+        options.extension.wikilinks_title_after_pipe = extension.wikilinks_title_after_pipe;
+        options.extension.wikilinks_title_before_pipe = extension.wikilinks_title_before_pipe;
+
+        options.parse.smart = self.parse.smart;
+        options.parse.default_info_string = self.parse.default_info_string.clone();
+        options.parse.relaxed_tasklist_matching = self.parse.relaxed_tasklist_matching;
+        options.parse.relaxed_autolinks = self.parse.relaxed_autolinks;
+
+        let render = &self.render;
+        options.render.hardbreaks = render.hardbreaks;
+        options.render.github_pre_lang = render.github_pre_lang;
+        options.render.full_info_string = render.full_info_string;
+        options.render.unsafe_ = render.unsafe_;
+        options.render.escape = render.escape;
+        options.render.sourcepos = render.sourcepos;
+        options.render.escaped_char_spans = render.escaped_char_spans;
+    }
+}
+
+/// A string setting that can also be switched on or off with a boolean.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrBool {
+    String(String),
+    Bool(bool),
+}
+
+fn string_or_bool<'de, D>(deserializer: D, when_true: &str) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match StringOrBool::deserialize(deserializer)? {
+        StringOrBool::String(value) => Some(value),
+        StringOrBool::Bool(true) => Some(when_true.to_string()),
+        StringOrBool::Bool(false) => None,
+    })
+}
+
+// `true` means anchors without a prefix
+fn header_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    string_or_bool(deserializer, "")
+}
+
+// `true` means YAML-style `---` front matter
+fn front_matter_delimiter<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    string_or_bool(deserializer, "---")
+}
@@ -0,0 +1,96 @@
+// Outline support injected into every rendered document.
+//
+// Headings are identified by the id of the anchor comrak puts in them, as
+// raw HTML, callouts or the front matter card may add headings of their own.
+// `window.dogmv.scrollToHeading(anchor, index)` scrolls to the heading with
+// that anchor, or to the n-th heading of the page if heading ids are off.
+// While the page is scrolled, the anchor of the current section (its index
+// without ids) is posted to the `dogmvOutline` message handler so the outline
+// panel can highlight it (-1 above the first heading).
+(function () {
+    "use strict";
+
+    // Headings within this distance of the top count as the current section
+    var OFFSET = 16;
+
+    var current = null;
+    var scheduled = false;
+
+    var HEADINGS = "h1, h2, h3, h4, h5, h6";
+
+    function headings() {
+        return document.body ? document.body.querySelectorAll(HEADINGS) : [];
+    }
+
+    function anchorOf(heading) {
+        var anchor = heading.querySelector("a.anchor[id]");
+        return anchor ? anchor.id : null;
+    }
+
+    function scrollToHeading(anchor, index) {
+        var heading = null;
+        if (anchor) {
+            var element = document.getElementById(anchor);
+            heading = element && (element.closest(HEADINGS) || element);
+        } else {
+            heading = headings()[index];
+        }
+        if (!heading) {
+            return false;
+        }
+        heading.scrollIntoView({ block: "start" });
+        return true;
+    }
+
+    function post(index) {
+        var handlers = window.webkit && window.webkit.messageHandlers;
+        if (handlers && handlers.dogmvOutline) {
+            handlers.dogmvOutline.postMessage(index);
+        }
+    }
+
+    function update() {
+        scheduled = false;
+        var list = headings();
+        // With heading ids on, headings without an anchor are not in the outline
+        var anchored = Array.prototype.some.call(list, function (heading) {
+            return anchorOf(heading) !== null;
+        });
+        var section = -1;
+        for (var i = 0; i < list.length; i++) {
+            if (list[i].getBoundingClientRect().top > OFFSET) {
+                break;
+            }
+            if (!anchored) {
+                section = i;
+            } else if (anchorOf(list[i]) !== null) {
+                section = anchorOf(list[i]);
+            }
+        }
+        if (section !== current) {
+            current = section;
+            post(section);
+        }
+    }
+
+    function schedule() {
+        if (!scheduled) {
+            scheduled = true;
+            window.requestAnimationFrame(update);
+        }
+    }
+
+    // A new or patched document may have different headings
+    function reset() {
+        current = null;
+        schedule();
+    }
+
+    window.dogmv = window.dogmv || {};
+    window.dogmv.scrollToHeading = scrollToHeading;
+
+    window.addEventListener("scroll", schedule, { passive: true });
+    window.addEventListener("resize", schedule);
+    document.addEventListener("DOMContentLoaded", reset);
+    document.addEventListener("dogmv:patched", reset);
+})();
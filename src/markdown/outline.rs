@@ -0,0 +1,154 @@
+use comrak::nodes::{AstNode, NodeValue};
+use comrak::Anchorizer;
+
+/// A heading of a rendered document, as listed in the outline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    /// 1 for `#`, up to 6 for `######`
+    pub level: u8,
+    /// Plain text of the heading, without markup
+    pub text: String,
+    /// `id` of the heading's anchor in the rendered page, which is how the
+    /// preview finds it; empty if heading ids are switched off
+    pub anchor: String,
+}
+
+/// The headings of a document being rendered, with the nodes they come from.
+pub struct Outline<'a> {
+    entries: Vec<(&'a AstNode<'a>, Heading)>,
+}
+
+impl<'a> Outline<'a> {
+    /// Collects the headings below `root` in document order.
+    pub fn collect(root: &'a AstNode<'a>) -> Self {
+        let entries = heading_nodes(root)
+            .map(|(node, level)| {
+                let heading = Heading {
+                    level,
+                    text: plain_text(node),
+                    anchor: String::new(),
+                };
+                (node, heading)
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Returns the headings with the anchors comrak gives them below `root`
+    /// with `header_ids` set to `prefix`.
+    ///
+    /// This has to run after all other changes to the document, as every
+    /// heading left in it counts towards the numbering of duplicate anchors.
+    pub fn finish(self, root: &'a AstNode<'a>, prefix: Option<&str>) -> Vec<Heading> {
+        let anchors = match prefix {
+            Some(prefix) => heading_anchors(root, prefix),
+            None => Vec::new(),
+        };
+        self.entries
+            .into_iter()
+            .map(|(node, mut heading)| {
+                if let Some((_, anchor)) = anchors.iter().find(|(n, _)| std::ptr::eq(*n, node)) {
+                    heading.anchor = anchor.clone();
+                }
+                heading
+            })
+            .collect()
+    }
+}
+
+/// Returns every heading below `root` with the `id` comrak gives its anchor
+/// when `header_ids` is set to `prefix`.
+pub fn heading_anchors<'a>(root: &'a AstNode<'a>, prefix: &str) -> Vec<(&'a AstNode<'a>, String)> {
+    // Anchors are derived from every heading, in order, to match comrak's
+    let mut anchorizer = Anchorizer::new();
+    heading_nodes(root)
+        .map(|(node, _)| {
+            let anchor = anchorizer.anchorize(anchor_text(node));
+            (node, format!("{}{}", prefix, anchor))
+        })
+        .collect()
+}
+
+fn heading_nodes<'a>(root: &'a AstNode<'a>) -> impl Iterator<Item = (&'a AstNode<'a>, u8)> {
+    root.descendants().filter_map(|node| match node.data.borrow().value {
+        NodeValue::Heading(ref heading) => Some((node, heading.level)),
+        _ => None,
+    })
+}
+
+/// Returns the text content of `node`, ignoring formatting.
+pub fn plain_text<'a>(node: &'a AstNode<'a>) -> String {
+    anchor_text(node).trim().to_string()
+}
+
+/// Returns the text comrak derives a heading's `id` from.
+///
+/// Unlike `plain_text` the result is not trimmed, as comrak doesn't trim it.
+pub fn anchor_text<'a>(node: &'a AstNode<'a>) -> String {
+    let mut text = String::new();
+    for descendant in node.descendants() {
+        match descendant.data.borrow().value {
+            NodeValue::Text(ref literal) => text.push_str(literal),
+            NodeValue::Code(ref code) => text.push_str(&code.literal),
+            NodeValue::Math(ref math) => text.push_str(&math.literal),
+            NodeValue::SoftBreak | NodeValue::LineBreak => text.push(' '),
+            _ => {}
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use comrak::{parse_document, Arena, Options};
+
+    fn heading(level: u8, text: &str, anchor: &str) -> Heading {
+        Heading {
+            level,
+            text: text.to_string(),
+            anchor: anchor.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_collect_headings() {
+        let arena = Arena::new();
+        let root = parse_document(
+            &arena,
+            "# Intro\n\nText\n\n## Using `dogmv` *fast*\n\n> ### Quoted\n\nSetext\n---\n",
+            &Options::default(),
+        );
+        assert_eq!(
+            Outline::collect(root).finish(root, Some("")),
+            vec![
+                heading(1, "Intro", "intro"),
+                heading(2, "Using dogmv fast", "using-dogmv-fast"),
+                heading(3, "Quoted", "quoted"),
+                heading(2, "Setext", "setext"),
+            ]
+        );
+        assert_eq!(
+            Outline::collect(root).finish(root, None),
+            vec![
+                heading(1, "Intro", ""),
+                heading(2, "Using dogmv fast", ""),
+                heading(3, "Quoted", ""),
+                heading(2, "Setext", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_anchors_count_every_heading() {
+        let arena = Arena::new();
+        let root = parse_document(&arena, "# Setup\n\n## Setup\n\n# Usage\n\n## Setup\n", &Options::default());
+        let outline = Outline::collect(root);
+        // A heading removed after collecting has no anchor and no longer
+        // counts towards the numbering of duplicates
+        root.children().next().unwrap().detach();
+        let headings = outline.finish(root, Some("user-content-"));
+        let anchors: Vec<_> = headings.iter().map(|h| h.anchor.as_str()).collect();
+        assert_eq!(anchors, ["", "user-content-setup", "user-content-usage", "user-content-setup-1"]);
+    }
+}
@@ -1,12 +1,43 @@
-use comrak::{markdown_to_html_with_plugins, Options, Plugins};
-use comrak::plugins::syntect::SyntectAdapter;
-use log::info;
+use comrak::nodes::{AstNode, NodeValue};
+use comrak::html::dangerous_url;
+use comrak::{format_html_with_plugins, parse_document, Arena, Options, Plugins};
+use log::{info, warn};
 use std::fs;
 use std::path::Path;
 use crate::error::{DogmvError, Result};
-use syntect::highlighting::ThemeSet;
+use crate::markdown::alerts::{mark_containers, render_alerts, ContainerNodes};
+use crate::markdown::diagrams::{render_external_diagrams, render_mermaid_blocks};
+use crate::markdown::front_matter::{front_matter_of, render_front_matter, TOML_DELIMITER};
+use crate::markdown::highlight::HighlightEngine;
+use crate::markdown::math::render_math;
+use crate::markdown::options::MarkdownOptions;
+use crate::markdown::outline::{Heading, Outline};
+use crate::markdown::theme::Theme;
+use crate::markdown::toc::{TocMarkers, TocSettings};
 use syntect::html::highlighted_html_for_string;
-use syntect::parsing::SyntaxSet;
+
+/// Script that lets the preview patch a loaded document in place on reload
+const LIVE_RELOAD_JS: &str = include_str!("live_reload.js");
+
+/// Script that renders mermaid diagrams with the locally served runtime
+const MERMAID_JS: &str = include_str!("mermaid.js");
+
+/// Script that sets the page title from the front matter card
+const FRONT_MATTER_JS: &str = include_str!("front_matter.js");
+
+/// Script that scrolls to headings and reports the current section to the outline
+const OUTLINE_JS: &str = include_str!("outline.js");
+
+/// Script reporting and applying scroll positions for the split view's scroll sync
+const SYNC_SCROLL_JS: &str = include_str!("sync_scroll.js");
+
+/// Styles for math, diagrams and other elements generated while rendering
+const DOCUMENT_CSS: &str = include_str!("document.css");
+
+/// Tags the GFM tagfilter extension disables in raw HTML
+const FILTERED_TAGS: &[&str] = &[
+    "title", "textarea", "style", "xmp", "iframe", "noembed", "noframes", "script", "plaintext",
+];
 
 /// Loads a Markdown file from the given path.
 ///
@@ -40,27 +71,201 @@ pub fn load_markdown(path: &Path) -> Result<String> {
 
 /// Renders Markdown to HTML with GitHub Flavored Markdown support and syntax highlighting.
 ///
+/// The comrak extensions are those configured in `MarkdownOptions::global`.
+///
 /// # Arguments
 /// * `markdown` - Markdown content string
+/// * `theme` - Theme whose syntect theme is used for code blocks
 ///
 /// # Returns
 /// HTML string with rendered Markdown
-pub fn render_markdown(markdown: &str) -> String {
+pub fn render_markdown(markdown: &str, theme: &Theme) -> String {
+    render_markdown_with(markdown, theme, MarkdownOptions::global()).0
+}
+
+/// Renders Markdown and also returns the document's headings for the outline.
+fn render_markdown_with(
+    markdown: &str,
+    theme: &Theme,
+    settings: &MarkdownOptions,
+) -> (String, Vec<Heading>) {
     info!("Rendering markdown ({} chars)", markdown.len());
 
+    let arena = Arena::new();
     let mut options = Options::default();
-    // Enable GitHub Flavored Markdown extensions
-    options.extension.strikethrough = true;
-    options.extension.table = true;
-    options.extension.tasklist = true;
-    options.extension.autolink = true;
-
-    // Create syntect adapter for syntax highlighting
-    let adapter = SyntectAdapter::new(Some("InspiredGitHub"));
+    let document = parse_markdown(&arena, markdown, settings, &mut options);
+    let (root, headings) = document.finish(&arena, &mut options);
+    (format_document(root, &options, theme), headings)
+}
+
+/// A parsed document part way through dogmv's rewrites.
+///
+/// The blocks written in the document are still comrak nodes (code blocks
+/// included), while front matter, containers and callouts are already
+/// rewritten. `finish` applies the remaining rewrites.
+pub(crate) struct ParsedDocument<'a> {
+    pub root: &'a AstNode<'a>,
+    pub outline: Outline<'a>,
+    toc: TocMarkers<'a>,
+}
+
+/// Parses `markdown` into `arena` and applies the structural rewrites.
+///
+/// `options` is set up from `settings`; after `ParsedDocument::finish` it
+/// holds the options the document has to be formatted with.
+pub(crate) fn parse_markdown<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    markdown: &str,
+    settings: &MarkdownOptions,
+    options: &mut Options,
+) -> ParsedDocument<'a> {
+    settings.apply_to(options);
+    // TOML front matter is recognized whatever the configured (YAML) delimiter is
+    if options.extension.front_matter_delimiter.is_some()
+        && markdown.lines().next().map(str::trim_end) == Some(TOML_DELIMITER)
+    {
+        options.extension.front_matter_delimiter = Some(TOML_DELIMITER.to_string());
+    }
+
+    // `:::` containers are not CommonMark; their fences become marker blocks
+    let (markdown, containers) = mark_containers(markdown);
+
+    let root = parse_document(arena, &markdown, options);
+    let containers = ContainerNodes::find(root, &containers);
+    let outline = Outline::collect(root);
+    let front_matter = front_matter_of(root);
+    let toc = TocMarkers::find(root, TocSettings::from_front_matter(front_matter.as_ref()));
+    // Table of contents links need heading ids
+    if !toc.is_empty() && options.extension.header_ids.is_none() {
+        options.extension.header_ids = Some(String::new());
+    }
+
+    // The front matter card, callouts, rendered math, diagrams and tables of
+    // contents are inserted as raw HTML, so raw output has to be switched on.
+    // Raw HTML written in the document itself is handled beforehand.
+    sanitize_raw_html(root, options);
+    render_front_matter(root, front_matter.as_ref());
+    containers.render();
+    render_alerts(arena, root);
+
+    ParsedDocument {
+        root,
+        outline,
+        toc,
+    }
+}
+
+impl<'a> ParsedDocument<'a> {
+    /// Applies the remaining rewrites (math, diagrams, table of contents) and
+    /// returns the root to format with `options`, along with the outline.
+    pub(crate) fn finish(
+        self,
+        arena: &'a Arena<AstNode<'a>>,
+        options: &mut Options,
+    ) -> (&'a AstNode<'a>, Vec<Heading>) {
+        render_math(self.root);
+        render_mermaid_blocks(self.root);
+        render_external_diagrams(arena, self.root);
+        let prefix = options.extension.header_ids.clone().unwrap_or_default();
+        self.toc.render(arena, self.root, &prefix);
+        let headings = self.outline.finish(self.root, options.extension.header_ids.as_deref());
+        options.render.unsafe_ = true;
+        options.render.escape = false;
+        // The tag filter would also hit generated HTML such as <title> in SVG
+        options.extension.tagfilter = false;
+        (self.root, headings)
+    }
+}
+
+/// Formats a finished document (see `ParsedDocument::finish`) to HTML.
+pub(crate) fn format_document<'a>(root: &'a AstNode<'a>, options: &Options, theme: &Theme) -> String {
+    // Reuse the shared syntect adapter for syntax highlighting
+    let adapter = HighlightEngine::global().adapter(&theme.highlight_theme);
     let mut plugins = Plugins::default();
-    plugins.render.codefence_syntax_highlighter = Some(&adapter);
+    plugins.render.codefence_syntax_highlighter = Some(adapter.as_ref());
 
-    markdown_to_html_with_plugins(markdown, &options, &plugins)
+    let mut html = Vec::new();
+    if let Err(e) = format_html_with_plugins(root, options, &mut html, &plugins) {
+        warn!("Failed to format markdown: {}", e);
+    }
+    String::from_utf8_lossy(&html).into_owned()
+}
+
+/// Applies the raw HTML settings in `options` to HTML written in the document.
+///
+/// Like comrak does when `render.unsafe_` is off, raw HTML is replaced by a
+/// comment (or shown escaped with `render.escape`), so that only HTML
+/// generated by dogmv is passed through verbatim. With `render.unsafe_` on,
+/// the GFM tag filter is applied here for the same reason.
+///
+/// Formatting always runs with `render.unsafe_` on, which also turns off
+/// comrak's check of link and image URLs. Unless the user switched it on,
+/// `javascript:`, `data:` and other dangerous URLs are blanked here instead.
+fn sanitize_raw_html<'a>(root: &'a AstNode<'a>, options: &Options) {
+    let unfiltered = !options.extension.tagfilter;
+    if options.render.unsafe_ && !options.render.escape && unfiltered {
+        return;
+    }
+    let check_urls = !options.render.unsafe_;
+
+    let sanitize = |literal: &str| {
+        if options.render.escape {
+            escape_html(literal)
+        } else if options.render.unsafe_ {
+            filter_tags(literal)
+        } else {
+            "<!-- raw HTML omitted -->".to_string()
+        }
+    };
+
+    for node in root.descendants() {
+        let mut ast = node.data.borrow_mut();
+        match ast.value {
+            NodeValue::HtmlBlock(ref mut block) => block.literal = sanitize(&block.literal),
+            NodeValue::HtmlInline(ref mut literal) => *literal = sanitize(literal),
+            NodeValue::Link(ref mut link) | NodeValue::Image(ref mut link)
+                if check_urls && dangerous_url(link.url.as_bytes()) =>
+            {
+                link.url.clear();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Disables the tags in `FILTERED_TAGS` by escaping their `<`, as GFM's tagfilter does.
+fn filter_tags(html: &str) -> String {
+    let mut filtered = String::with_capacity(html.len());
+    for (i, c) in html.char_indices() {
+        if c == '<' && is_filtered_tag(&html[i + 1..]) {
+            filtered.push_str("&lt;");
+        } else {
+            filtered.push(c);
+        }
+    }
+    filtered
+}
+
+fn is_filtered_tag(tag: &str) -> bool {
+    let tag = tag.strip_prefix('/').unwrap_or(tag);
+    FILTERED_TAGS.iter().any(|name| {
+        match tag.get(..name.len()) {
+            Some(prefix) if prefix.eq_ignore_ascii_case(name) => {
+                let rest = &tag[name.len()..];
+                rest.is_empty()
+                    || rest.starts_with(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Escapes `&`, `<`, `>` and `"` for use in HTML text and attribute values.
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
 }
 
 /// Determines if a file is a Markdown file based on extension.
@@ -79,200 +284,109 @@ pub fn is_markdown_file(path: &Path) -> bool {
     }
 }
 
+/// Determines if dogmv can display a file: Markdown or source code of a known syntax.
+pub fn is_supported_file(path: &Path) -> bool {
+    is_markdown_file(path) || HighlightEngine::global().syntax_for_path(path).is_some()
+}
+
 /// Renders source code to HTML with syntax highlighting.
 ///
 /// # Arguments
 /// * `code` - Source code content
 /// * `path` - File path (used to detect syntax by extension)
+/// * `theme` - Theme whose syntect theme is used for highlighting
 ///
 /// # Returns
 /// HTML string with syntax highlighted code
-pub fn render_source_code(code: &str, path: &Path) -> String {
+pub fn render_source_code(code: &str, path: &Path, theme: &Theme) -> String {
     info!("Rendering source code ({} chars) for {}", code.len(), path.display());
 
-    let syntax_set = SyntaxSet::load_defaults_newlines();
-    let theme_set = ThemeSet::load_defaults();
-    let theme = &theme_set.themes["InspiredGitHub"];
+    let engine = HighlightEngine::global();
+    let syntax_set = engine.syntax_set();
+    let highlight_theme = engine.theme(&theme.highlight_theme);
 
-    // Try to find syntax by file extension
-    let syntax = if let Some(ext) = path.extension() {
-        syntax_set
-            .find_syntax_by_extension(&ext.to_string_lossy())
-            .or_else(|| syntax_set.find_syntax_by_first_line(code))
-            .unwrap_or_else(|| syntax_set.find_syntax_plain_text())
-    } else {
-        syntax_set.find_syntax_plain_text()
-    };
+    // Find syntax by the user's syntax map, file extension or first line
+    let syntax = engine.find_syntax(path, code);
 
     info!("Using syntax: {}", syntax.name);
 
     // Generate highlighted HTML
-    highlighted_html_for_string(code, &syntax_set, syntax, theme)
+    highlighted_html_for_string(code, syntax_set, syntax, highlight_theme)
         .unwrap_or_else(|e| {
             info!("Failed to highlight code: {}, falling back to plain text", e);
             format!("<pre style='background-color: #f6f8fa; padding: 16px; overflow: auto; border-radius: 6px;'><code>{}</code></pre>",
-                    code.replace('&', "&amp;")
-                        .replace('<', "&lt;")
-                        .replace('>', "&gt;")
-                        .replace('"', "&quot;"))
+                    escape_html(code))
         })
 }
 
-/// Creates a complete HTML document with GitHub-style CSS.
+/// Renders the contents of a file for the preview.
+///
+/// Markdown files are rendered as Markdown, everything else as highlighted
+/// source code. Returns the HTML body, which `create_html` wraps into a
+/// document, and the headings of Markdown files.
+///
+/// # Arguments
+/// * `content` - File contents
+/// * `path` - File path (used to pick the renderer and the syntax)
+/// * `theme` - Theme used for syntax highlighting
+pub fn render_document(content: &str, path: &Path, theme: &Theme) -> (String, Vec<Heading>) {
+    if is_markdown_file(path) {
+        render_markdown_with(content, theme, MarkdownOptions::global())
+    } else {
+        (render_source_code(content, path, theme), Vec::new())
+    }
+}
+
+/// Creates a complete HTML document styled with the given theme.
 ///
 /// # Arguments
 /// * `body` - HTML body content (rendered Markdown)
 /// * `base_path` - Base path for resolving relative links and images
+/// * `theme` - Theme providing the document stylesheet
 ///
 /// # Returns
 /// Complete HTML document string
-pub fn create_html(body: &str, base_path: &str) -> String {
+pub fn create_html(body: &str, base_path: &str, theme: &Theme) -> String {
     format!(
         r#"<!DOCTYPE html>
-<html>
+<html data-dogmv-theme="{}">
 <head>
     <meta charset="UTF-8">
     <base href="file://{}/">
     <style>
-        body {{
-            font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", Helvetica, Arial, sans-serif;
-            line-height: 1.6;
-            padding: 20px;
-            max-width: 900px;
-            margin: 0 auto;
-            color: #24292e;
-        }}
-        h1, h2, h3, h4, h5, h6 {{
-            margin-top: 24px;
-            margin-bottom: 16px;
-            font-weight: 600;
-            line-height: 1.25;
-        }}
-        h1 {{
-            font-size: 2em;
-            border-bottom: 1px solid #eaecef;
-            padding-bottom: 0.3em;
-        }}
-        h2 {{
-            font-size: 1.5em;
-            border-bottom: 1px solid #eaecef;
-            padding-bottom: 0.3em;
-        }}
-        h3 {{ font-size: 1.25em; }}
-        h4 {{ font-size: 1em; }}
-        h5 {{ font-size: 0.875em; }}
-        h6 {{ font-size: 0.85em; color: #6a737d; }}
-
-        p {{ margin-top: 0; margin-bottom: 16px; }}
-
-        a {{
-            color: #0366d6;
-            text-decoration: none;
-        }}
-        a:hover {{
-            text-decoration: underline;
-        }}
-
-        code {{
-            background-color: rgba(27,31,35,0.05);
-            padding: 0.2em 0.4em;
-            margin: 0;
-            font-size: 85%;
-            border-radius: 3px;
-            font-family: "SFMono-Regular", Consolas, "Liberation Mono", Menlo, monospace;
-        }}
-
-        pre {{
-            padding: 16px;
-            overflow: auto;
-            font-size: 85%;
-            line-height: 1.45;
-            border-radius: 6px;
-            margin-top: 0;
-            margin-bottom: 16px;
-            background-color: #f6f8fa !important;
-        }}
-
-        pre.syntect {{
-            background-color: #f6f8fa !important;
-        }}
-
-        pre:not(.syntect) {{
-            background-color: #f6f8fa !important;
-        }}
-
-        pre code {{
-            background-color: transparent !important;
-            padding: 0;
-            margin: 0;
-            font-size: 100%;
-            border-radius: 0;
-        }}
-
-        blockquote {{
-            padding: 0 1em;
-            color: #6a737d;
-            border-left: 0.25em solid #dfe2e5;
-            margin: 0 0 16px 0;
-        }}
-
-        table {{
-            border-collapse: collapse;
-            width: 100%;
-            margin-bottom: 16px;
-        }}
-
-        table tr {{
-            background-color: #fff;
-            border-top: 1px solid #c6cbd1;
-        }}
-
-        table tr:nth-child(2n) {{
-            background-color: #f6f8fa;
-        }}
-
-        table th, table td {{
-            padding: 6px 13px;
-            border: 1px solid #dfe2e5;
-        }}
-
-        table th {{
-            font-weight: 600;
-        }}
-
-        ul, ol {{
-            margin-top: 0;
-            margin-bottom: 16px;
-            padding-left: 2em;
-        }}
-
-        li + li {{
-            margin-top: 0.25em;
-        }}
-
-        img {{
-            max-width: 100%;
-            box-sizing: content-box;
-        }}
-
-        hr {{
-            height: 0.25em;
-            padding: 0;
-            margin: 24px 0;
-            background-color: #e1e4e8;
-            border: 0;
-        }}
-
-        input[type="checkbox"] {{
-            margin-right: 0.5em;
-        }}
+{}
+{}
     </style>
+    <script>
+{}
+    </script>
+    <script>
+{}
+    </script>
+    <script>
+{}
+    </script>
+    <script>
+{}
+    </script>
+    <script>
+{}
+    </script>
 </head>
 <body>
 {}
 </body>
 </html>"#,
-        base_path, body
+        if theme.dark { "dark" } else { "light" },
+        base_path,
+        DOCUMENT_CSS,
+        theme.css,
+        LIVE_RELOAD_JS,
+        MERMAID_JS,
+        FRONT_MATTER_JS,
+        OUTLINE_JS,
+        SYNC_SCROLL_JS,
+        body
     )
 }
 
@@ -284,7 +398,7 @@ mod tests {
     #[test]
     fn test_render_markdown() {
         let markdown = "# Hello\n\nThis is a test.";
-        let html = render_markdown(markdown);
+        let html = render_markdown(markdown, &Theme::light());
         assert!(html.contains("<h1>"));
         assert!(html.contains("Hello"));
         assert!(html.contains("<p>"));
@@ -295,20 +409,20 @@ mod tests {
     fn test_render_markdown_gfm() {
         // Test table (need proper markdown format)
         let markdown = "| A | B |\n|---|---|\n| 1 | 2 |";
-        let html = render_markdown(markdown);
+        let html = render_markdown(markdown, &Theme::light());
         assert!(html.contains("<table>"));
         assert!(html.contains("<td>"));
 
         // Test strikethrough
         let markdown2 = "~~strikethrough~~";
-        let html2 = render_markdown(markdown2);
+        let html2 = render_markdown(markdown2, &Theme::light());
         assert!(html2.contains("<del>") || html2.contains("strikethrough"));
     }
 
     #[test]
     fn test_create_html() {
         let body = "<h1>Test</h1>";
-        let html = create_html(body, "/test/path");
+        let html = create_html(body, "/test/path", &Theme::light());
         assert!(html.contains("<!DOCTYPE html>"));
         assert!(html.contains("<h1>Test</h1>"));
         assert!(html.contains("file:///test/path/"));
@@ -316,11 +430,50 @@ mod tests {
 
     #[test]
     fn test_create_html_includes_css() {
-        let html = create_html("", "/");
+        let html = create_html("", "/", &Theme::light());
         assert!(html.contains("<style>"));
         assert!(html.contains("font-family"));
     }
 
+    #[test]
+    fn test_create_html_includes_live_reload_script() {
+        let html = create_html("<p>Body</p>", "/", &Theme::light());
+        assert!(html.contains("window.dogmv.patch"));
+        // The script must live in <head> so that <body> only holds content blocks
+        assert!(html.find("window.dogmv.patch").unwrap() < html.find("<body>").unwrap());
+    }
+
+    #[test]
+    fn test_render_document_returns_headings() {
+        let (html, headings) =
+            render_document("# Title
+
+## Usage
+", &PathBuf::from("doc.md"), &Theme::light());
+        assert!(html.contains("Usage</h2>"));
+        let texts: Vec<_> = headings.iter().map(|heading| heading.text.as_str()).collect();
+        assert_eq!(texts, ["Title", "Usage"]);
+
+        // Source files have no outline
+        let (_, headings) =
+            render_document("# not a heading
+", &PathBuf::from("script.sh"), &Theme::light());
+        assert!(headings.is_empty());
+    }
+
+    #[test]
+    fn test_theme_changes_css_and_highlighting() {
+        let code = "fn main() {}";
+        let path = PathBuf::from("test.rs");
+        let light = render_source_code(code, &path, &Theme::light());
+        let dark = render_source_code(code, &path, &Theme::dark());
+        assert_ne!(light, dark);
+
+        let html = create_html("", "/", &Theme::dark());
+        assert!(html.contains(&Theme::dark().css));
+        assert!(!html.contains(&Theme::light().css));
+    }
+
     #[test]
     fn test_is_markdown_file() {
         assert!(is_markdown_file(Path::new("test.md")));
@@ -331,11 +484,20 @@ mod tests {
         assert!(!is_markdown_file(Path::new("test")));
     }
 
+    #[test]
+    fn test_is_supported_file() {
+        assert!(is_supported_file(Path::new("docs/setup.md")));
+        assert!(is_supported_file(Path::new("src/main.rs")));
+        assert!(is_supported_file(Path::new("Makefile")));
+        assert!(!is_supported_file(Path::new("logo.png")));
+        assert!(!is_supported_file(Path::new("manual.pdf")));
+    }
+
     #[test]
     fn test_render_source_code() {
         let code = "fn main() {\n    println!(\"Hello, world!\");\n}";
         let path = PathBuf::from("test.rs");
-        let html = render_source_code(code, &path);
+        let html = render_source_code(code, &path, &Theme::light());
 
         // Should contain HTML
         assert!(html.contains("<pre"));
@@ -348,10 +510,391 @@ mod tests {
     fn test_render_source_code_unknown_extension() {
         let code = "some text content";
         let path = PathBuf::from("test.unknown");
-        let html = render_source_code(code, &path);
+        let html = render_source_code(code, &path, &Theme::light());
 
         // Should still return HTML even with unknown extension
         assert!(html.contains("<pre"));
         assert!(html.contains("some text content"));
     }
+
+    #[test]
+    fn test_raw_html_is_still_omitted() {
+        let html = render_markdown("<div>raw</div>\n\nText with <b>inline</b> HTML", &Theme::light());
+        assert!(!html.contains("<div>"));
+        assert!(!html.contains("<b>"));
+        assert!(html.contains("<!-- raw HTML omitted -->"));
+    }
+
+    #[test]
+    fn test_dangerous_link_urls_are_blanked() {
+        let markdown = "[link](javascript:alert(1)) ![image](javascript:alert(2)) \
+                        [data](data:text/html;base64,PHNjcmlwdD4=) ![pixel](data:image/png;base64,iVBO)";
+        let html = render_markdown(markdown, &Theme::light());
+        assert!(!html.contains("javascript:"), "{}", html);
+        assert!(!html.contains("data:text/html"), "{}", html);
+        assert!(html.contains("<a href=\"\">link</a>"), "{}", html);
+        assert!(html.contains("<img src=\"\" alt=\"image\""), "{}", html);
+        // Inline images are harmless, as in comrak
+        assert!(html.contains("data:image/png;base64,iVBO"), "{}", html);
+
+        // With raw HTML allowed by the user, URLs are left alone as comrak does
+        let html = render_with("[link](javascript:void(0))", |o| o.render.unsafe_ = true);
+        assert!(html.contains("javascript:void(0)"));
+    }
+
+    #[test]
+    fn test_render_math() {
+        let html = render_markdown("Energy $E = mc^2$ and\n\n$$\\sum_{i=1}^n i$$", &Theme::light());
+        assert!(html.contains("<math"));
+        assert!(html.contains("display=\"block\""));
+        assert!(!html.contains("$E"));
+    }
+
+    #[test]
+    fn test_malformed_math_shows_error_span() {
+        let html = render_markdown("Broken $\\frac{1}{$ formula\n\nNext paragraph", &Theme::light());
+        assert!(html.contains("class=\"math-error\""));
+        assert!(html.contains("\\frac{1}{"));
+        // The rest of the page still renders
+        assert!(html.contains("Next paragraph"));
+    }
+
+    #[test]
+    fn test_mermaid_fence_becomes_diagram() {
+        let markdown = "```mermaid\ngraph TD\n  A-->B\n```\n\n```rust\nfn main() {}\n```";
+        let html = render_markdown(markdown, &Theme::light());
+        assert!(html.contains("<div class=\"mermaid-diagram\">"));
+        assert!(html.contains("A--&gt;B"));
+        // Other fences are still highlighted
+        assert!(html.contains("<pre style="));
+
+        let page = create_html(&html, "/", &Theme::dark());
+        assert!(page.contains("data-dogmv-theme=\"dark\""));
+        assert!(page.contains("dogmv://assets/mermaid.min.js"));
+    }
+
+    fn render_with(markdown: &str, configure: impl FnOnce(&mut MarkdownOptions)) -> String {
+        let mut settings = MarkdownOptions::default();
+        configure(&mut settings);
+        render_markdown_with(markdown, &Theme::light(), &settings).0
+    }
+
+    #[test]
+    fn test_option_strikethrough() {
+        assert!(render_with("~~gone~~", |_| {}).contains("<del>gone</del>"));
+        assert!(!render_with("~~gone~~", |o| o.extension.strikethrough = false).contains("<del>"));
+    }
+
+    #[test]
+    fn test_option_tagfilter() {
+        let markdown = "<script>alert(1)</script>\n\nText <b>bold</b>";
+        let filtered = render_with(markdown, |o| o.render.unsafe_ = true);
+        assert!(filtered.contains("&lt;script>alert(1)&lt;/script>"));
+        // Other tags are passed through
+        assert!(filtered.contains("<b>bold</b>"));
+
+        let unfiltered = render_with(markdown, |o| {
+            o.render.unsafe_ = true;
+            o.extension.tagfilter = false;
+        });
+        assert!(unfiltered.contains("<script>alert(1)</script>"));
+    }
+
+    #[test]
+    fn test_option_table() {
+        let markdown = "| A | B |\n|---|---|\n| 1 | 2 |";
+        assert!(render_with(markdown, |_| {}).contains("<table>"));
+        assert!(!render_with(markdown, |o| o.extension.table = false).contains("<table>"));
+    }
+
+    #[test]
+    fn test_option_autolink() {
+        let markdown = "Visit www.example.com today";
+        assert!(render_with(markdown, |_| {}).contains("<a href=\"http://www.example.com\">"));
+        assert!(!render_with(markdown, |o| o.extension.autolink = false).contains("<a "));
+    }
+
+    #[test]
+    fn test_option_tasklist() {
+        let markdown = "- [x] done\n- [ ] todo";
+        assert!(render_with(markdown, |_| {}).contains("type=\"checkbox\""));
+        assert!(!render_with(markdown, |o| o.extension.tasklist = false).contains("checkbox"));
+    }
+
+    #[test]
+    fn test_option_superscript() {
+        let markdown = "e = mc^2^";
+        assert!(!render_with(markdown, |_| {}).contains("<sup>"));
+        assert!(render_with(markdown, |o| o.extension.superscript = true).contains("<sup>2</sup>"));
+    }
+
+    #[test]
+    fn test_option_header_ids() {
+        let markdown = "# Hello World";
+        assert!(render_with(markdown, |_| {}).contains("id=\"hello-world\""));
+
+        let prefixed = render_with(markdown, |o| {
+            o.extension.header_ids = Some("user-content-".to_string())
+        });
+        assert!(prefixed.contains("id=\"user-content-hello-world\""));
+
+        assert!(!render_with(markdown, |o| o.extension.header_ids = None).contains("id="));
+    }
+
+    #[test]
+    fn test_option_footnotes() {
+        let markdown = "Claim[^1]\n\n[^1]: Source";
+        assert!(render_with(markdown, |_| {}).contains("class=\"footnotes\""));
+        assert!(!render_with(markdown, |o| o.extension.footnotes = false).contains("footnotes"));
+    }
+
+    #[test]
+    fn test_option_description_lists() {
+        let markdown = "Term\n\n: Definition";
+        assert!(!render_with(markdown, |_| {}).contains("<dl>"));
+
+        let html = render_with(markdown, |o| o.extension.description_lists = true);
+        assert!(html.contains("<dl>"));
+        assert!(html.contains("<dt>Term</dt>"));
+    }
+
+    #[test]
+    fn test_option_front_matter_delimiter() {
+        let markdown = "---\ntitle: Notes\n---\n\n# Body";
+        let html = render_with(markdown, |_| {});
+        assert!(!html.contains("title: Notes"));
+        assert!(html.contains("Body"));
+
+        let html = render_with(markdown, |o| o.extension.front_matter_delimiter = None);
+        assert!(html.contains("title: Notes"));
+    }
+
+    #[test]
+    fn test_front_matter_card() {
+        let html = render_markdown(
+            "---\ntitle: Notes\nstatus: draft\n---\n\n# Body",
+            &Theme::light(),
+        );
+        assert!(html.starts_with("<header class=\"front-matter\" data-title=\"Notes\">"));
+        assert!(html.contains("<dt>status</dt><dd>draft</dd>"));
+        assert!(html.contains("Body</h1>"));
+
+        // TOML front matter is detected with the default delimiter
+        let html = render_markdown("+++\ntitle = \"Plan\"\n+++\n\nText", &Theme::light());
+        assert!(html.contains("data-title=\"Plan\""));
+        assert!(!html.contains("+++"));
+
+        let page = create_html(&html, "/", &Theme::light());
+        assert!(page.contains("document.title"));
+    }
+
+    #[test]
+    fn test_github_alerts() {
+        let markdown = "> [!WARNING]\n> Mind the **gap**\n\n> [!note]\n>\n> Second paragraph\n\n> [!TIP] inline\n> Plain quote";
+        let html = render_markdown(markdown, &Theme::light());
+        assert!(html.contains("<div class=\"markdown-alert markdown-alert-warning\">"));
+        assert!(html.contains("Warning</p>\n<p>Mind the <strong>gap</strong></p>\n</div>"));
+        assert!(html.contains("<div class=\"markdown-alert markdown-alert-note\">"));
+        assert!(html.contains("<p>Second paragraph</p>"));
+        assert!(!html.contains("[!WARNING]"));
+        // The marker has to be alone on its line
+        assert_eq!(html.matches("<blockquote>").count(), 1);
+        assert!(html.contains("[!TIP] inline"));
+    }
+
+    #[test]
+    fn test_container_blocks() {
+        let markdown = "::: warning Read this first\nText with *emphasis*\n\n- a list\n:::\n\n::: danger\nCareful\n:::";
+        let html = render_markdown(markdown, &Theme::light());
+        assert!(html.contains(
+            "<div class=\"markdown-alert markdown-alert-warning\">\n<p class=\"markdown-alert-title\">"
+        ));
+        assert!(html.contains("Read this first</p>"));
+        assert!(html.contains("<li>a list</li>\n</ul>\n</div>"));
+        assert!(html.contains("<div class=\"markdown-alert markdown-alert-caution\">"));
+        assert!(html.contains("Caution</p>\n<p>Careful</p>\n</div>"));
+        assert!(!html.contains(":::"));
+        assert!(!html.contains("raw HTML omitted"));
+    }
+
+    #[test]
+    fn test_toc_marker() {
+        let markdown = "# Guide
+
+[TOC]
+
+## Setup `cargo`
+
+### Linux
+
+## Setup `cargo`
+";
+        let html = render_markdown(markdown, &Theme::light());
+        assert!(html.contains("<nav class=\"toc\">"));
+        assert!(!html.contains("[TOC]"));
+        // Links match the heading ids, including those of duplicate headings
+        for anchor in ["guide", "setup-cargo", "linux", "setup-cargo-1"] {
+            assert!(html.contains(&format!("<a href=\"#{}\">", anchor)));
+            assert!(html.contains(&format!("id=\"{}\"", anchor)));
+        }
+    }
+
+    #[test]
+    fn test_toc_comment_marker_with_depth_limit() {
+        let markdown = "---\ntoc_max_depth: 2\n---\n\n<!-- toc -->\n- [Stale](#stale)\n<!-- tocstop -->\n\n# One\n\n## Two\n\n### Three\n";
+        // Works with raw HTML and heading ids disabled
+        let html = render_with(markdown, |o| o.extension.header_ids = None);
+        assert!(html.contains("<a href=\"#one\">One</a>"));
+        assert!(html.contains("<a href=\"#two\">Two</a>"));
+        assert!(!html.contains("<a href=\"#three\">Three</a>"));
+        assert!(html.contains("id=\"three\""));
+        assert!(!html.contains("Stale"));
+        assert!(!html.contains("raw HTML omitted"));
+    }
+
+    #[test]
+    fn test_option_multiline_block_quotes() {
+        let markdown = ">>>\nquoted\n\nparagraphs\n>>>";
+        // Without the extension `>>>` opens nested one-line quotes
+        assert!(!render_with(markdown, |_| {}).contains("<p>paragraphs</p>\n</blockquote>"));
+
+        let html = render_with(markdown, |o| o.extension.multiline_block_quotes = true);
+        assert_eq!(html.matches("<blockquote>").count(), 1);
+        assert!(html.contains("<p>paragraphs</p>\n</blockquote>"));
+    }
+
+    #[test]
+    fn test_option_math_dollars() {
+        let markdown = "Cost $x^2$ here";
+        assert!(render_with(markdown, |_| {}).contains("<math"));
+
+        let html = render_with(markdown, |o| o.extension.math_dollars = false);
+        assert!(!html.contains("<math"));
+        assert!(html.contains("$x^2$"));
+    }
+
+    #[test]
+    fn test_option_math_code() {
+        let markdown = "Cost $`x^2`$ here";
+        assert!(!render_with(markdown, |o| o.extension.math_dollars = false).contains("<math"));
+        assert!(render_with(markdown, |o| o.extension.math_code = true).contains("<math"));
+    }
+
+    #[test]
+    fn test_option_wikilinks() {
+        assert!(!render_with("[[page|Title]]", |_| {}).contains("<a "));
+
+        let after = render_with("[[page|Title]]", |o| o.extension.wikilinks_title_after_pipe = true);
+        assert!(after.contains("href=\"page\""));
+        assert!(after.contains(">Title</a>"));
+
+        let before = render_with("[[Title|page]]", |o| o.extension.wikilinks_title_before_pipe = true);
+        assert!(before.contains("href=\"page\""));
+        assert!(before.contains(">Title</a>"));
+    }
+
+    #[test]
+    fn test_option_smart() {
+        let markdown = "\"Quoted\" -- dashed";
+        assert!(!render_with(markdown, |_| {}).contains('\u{201c}'));
+
+        let html = render_with(markdown, |o| o.parse.smart = true);
+        assert!(html.contains('\u{201c}'));
+        assert!(html.contains('\u{2013}'));
+    }
+
+    #[test]
+    fn test_option_default_info_string() {
+        let markdown = "```\nfn main() {}\n```";
+        assert!(!render_with(markdown, |_| {}).contains("language-rust"));
+
+        let html = render_with(markdown, |o| o.parse.default_info_string = Some("rust".to_string()));
+        assert!(html.contains("language-rust"));
+    }
+
+    #[test]
+    fn test_option_relaxed_tasklist_matching() {
+        let markdown = "- [+] item";
+        assert!(!render_with(markdown, |_| {}).contains("checkbox"));
+        assert!(render_with(markdown, |o| o.parse.relaxed_tasklist_matching = true).contains("checkbox"));
+    }
+
+    #[test]
+    fn test_option_relaxed_autolinks() {
+        let markdown = "Join irc://irc.example.com/dogmv";
+        assert!(!render_with(markdown, |_| {}).contains("<a "));
+
+        let html = render_with(markdown, |o| o.parse.relaxed_autolinks = true);
+        assert!(html.contains("<a href=\"irc://irc.example.com/dogmv\">"));
+    }
+
+    #[test]
+    fn test_option_hardbreaks() {
+        let markdown = "first\nsecond";
+        assert!(!render_with(markdown, |_| {}).contains("<br"));
+        assert!(render_with(markdown, |o| o.render.hardbreaks = true).contains("<br />"));
+    }
+
+    #[test]
+    fn test_option_github_pre_lang() {
+        let markdown = "```rust\nfn main() {}\n```";
+        assert!(!render_with(markdown, |_| {}).contains("lang=\"rust\""));
+        assert!(render_with(markdown, |o| o.render.github_pre_lang = true).contains("lang=\"rust\""));
+    }
+
+    #[test]
+    fn test_option_full_info_string() {
+        let markdown = "```rust title=main.rs\nfn main() {}\n```";
+        assert!(!render_with(markdown, |_| {}).contains("data-meta"));
+
+        let html = render_with(markdown, |o| o.render.full_info_string = true);
+        assert!(html.contains("data-meta=\"title=main.rs\""));
+    }
+
+    #[test]
+    fn test_option_unsafe() {
+        let markdown = "Text <b>bold</b>\n\n<div class=\"note\">raw</div>";
+        let html = render_with(markdown, |o| o.render.unsafe_ = true);
+        assert!(html.contains("<b>bold</b>"));
+        assert!(html.contains("<div class=\"note\">raw</div>"));
+    }
+
+    #[test]
+    fn test_option_escape() {
+        let html = render_with("Text <b>bold</b>", |o| o.render.escape = true);
+        assert!(html.contains("&lt;b&gt;bold&lt;/b&gt;"));
+
+        // Escaping wins over passing raw HTML through, as in comrak
+        let html = render_with("Text <b>bold</b>", |o| {
+            o.render.escape = true;
+            o.render.unsafe_ = true;
+        });
+        assert!(!html.contains("<b>"));
+    }
+
+    #[test]
+    fn test_option_sourcepos() {
+        let markdown = "# Title\n\nParagraph";
+        assert!(!render_with(markdown, |_| {}).contains("data-sourcepos"));
+
+        let html = render_with(markdown, |o| o.render.sourcepos = true);
+        assert!(html.contains("data-sourcepos=\"3:1-3:9\""));
+    }
+
+    #[test]
+    fn test_option_escaped_char_spans() {
+        let markdown = "Not \\*emphasis\\*";
+        assert!(!render_with(markdown, |_| {}).contains("data-escaped-char"));
+
+        let html = render_with(markdown, |o| o.render.escaped_char_spans = true);
+        assert!(html.contains("<span data-escaped-char>*</span>"));
+    }
+
+    #[test]
+    fn test_filter_tags() {
+        assert_eq!(filter_tags("<SCRIPT src=x></script>"), "&lt;SCRIPT src=x>&lt;/script>");
+        assert_eq!(filter_tags("<title/>"), "&lt;title/>");
+        // Only whole tag names are filtered
+        assert_eq!(filter_tags("<scripted><b>"), "<scripted><b>");
+    }
 }
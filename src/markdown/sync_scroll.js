@@ -0,0 +1,87 @@
+// Scroll sync between the panes of the split view.
+//
+// While the user scrolls the page, its position is posted to the
+// `dogmvScroll` message handler as JSON: the relative position (`ratio`),
+// the anchor of the heading at the top of the page and how far the page is
+// scrolled past that heading (`offset`). `window.dogmv.syncScroll(position,
+// byAnchor)` scrolls the page to a position posted by the other pane.
+(function () {
+    "use strict";
+
+    // Scroll events this soon after a sync are its echo and are not posted
+    var ECHO_INTERVAL = 150;
+
+    var syncedAt = 0;
+    var scheduled = false;
+
+    function maxScroll() {
+        return Math.max(0, document.documentElement.scrollHeight - window.innerHeight);
+    }
+
+    function anchorOf(heading) {
+        var anchor = heading.querySelector("a.anchor[id]");
+        return anchor ? anchor.id : heading.id;
+    }
+
+    // The last heading at or above the top of the page
+    function topHeading() {
+        var headings = document.body ? document.body.querySelectorAll("h1, h2, h3, h4, h5, h6") : [];
+        var current = null;
+        for (var i = 0; i < headings.length; i++) {
+            if (headings[i].getBoundingClientRect().top > 1) {
+                break;
+            }
+            current = headings[i];
+        }
+        return current;
+    }
+
+    function position() {
+        var max = maxScroll();
+        var heading = topHeading();
+        return {
+            ratio: max > 0 ? window.scrollY / max : 0,
+            anchor: heading ? anchorOf(heading) : "",
+            offset: heading ? -heading.getBoundingClientRect().top : 0
+        };
+    }
+
+    function post() {
+        scheduled = false;
+        var handlers = window.webkit && window.webkit.messageHandlers;
+        if (handlers && handlers.dogmvScroll) {
+            handlers.dogmvScroll.postMessage(JSON.stringify(position()));
+        }
+    }
+
+    function schedule() {
+        if (Date.now() - syncedAt < ECHO_INTERVAL) {
+            return;
+        }
+        if (!scheduled) {
+            scheduled = true;
+            window.requestAnimationFrame(post);
+        }
+    }
+
+    function syncScroll(position, byAnchor) {
+        var target = null;
+        if (byAnchor && position.anchor) {
+            var element = document.getElementById(position.anchor);
+            var heading = element && element.closest("h1, h2, h3, h4, h5, h6");
+            if (heading) {
+                target = window.scrollY + heading.getBoundingClientRect().top + position.offset;
+            }
+        }
+        if (target === null) {
+            target = position.ratio * maxScroll();
+        }
+        syncedAt = Date.now();
+        window.scrollTo(0, target);
+    }
+
+    window.dogmv = window.dogmv || {};
+    window.dogmv.syncScroll = syncScroll;
+
+    window.addEventListener("scroll", schedule, { passive: true });
+})();
@@ -0,0 +1,422 @@
+use crate::error::{DogmvError, Result};
+use crate::markdown::highlight::{HighlightEngine, DEFAULT_THEME};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use syntect::highlighting::{Theme as SyntectTheme, ThemeSet};
+
+/// Syntect theme paired with the built-in dark stylesheet
+const DARK_HIGHLIGHT_THEME: &str = "base16-ocean.dark";
+
+/// Prefix of the ids of user themes, keeping them apart from the built-in
+/// themes and syntect's own highlighting themes
+const USER_THEME_PREFIX: &str = "user:";
+
+/// A document theme: the stylesheet of the rendered page together with the
+/// syntect theme used for its code blocks, so that both always match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    /// Identifies the theme: "Light", "Dark" or "user:<file stem>" (see `ThemeChoice::id`)
+    pub name: String,
+    /// CSS inserted into every generated document
+    pub css: String,
+    /// Name of the syntect theme for code blocks and source files
+    pub highlight_theme: String,
+    /// Whether the theme has a dark background
+    pub dark: bool,
+}
+
+impl Theme {
+    /// The built-in GitHub-style light theme.
+    pub fn light() -> Self {
+        Self {
+            name: "Light".to_string(),
+            css: include_str!("themes/light.css").to_string(),
+            highlight_theme: DEFAULT_THEME.to_string(),
+            dark: false,
+        }
+    }
+
+    /// The built-in GitHub-style dark theme.
+    pub fn dark() -> Self {
+        Self {
+            name: "Dark".to_string(),
+            css: include_str!("themes/dark.css").to_string(),
+            highlight_theme: DARK_HIGHLIGHT_THEME.to_string(),
+            dark: true,
+        }
+    }
+
+    /// Returns the built-in theme matching the desktop's color scheme.
+    pub fn for_color_scheme(prefer_dark: bool) -> Self {
+        if prefer_dark {
+            Self::dark()
+        } else {
+            Self::light()
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::light()
+    }
+}
+
+/// A theme offered in the theme picker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThemeChoice {
+    Light,
+    Dark,
+    /// Theme files from the user's themes directory sharing the same file stem.
+    ///
+    /// `house.css` replaces the built-in stylesheet and `house.tmTheme` the
+    /// code highlighting theme; either may be missing.
+    User {
+        name: String,
+        css: Option<PathBuf>,
+        highlight: Option<PathBuf>,
+    },
+}
+
+impl ThemeChoice {
+    /// Name shown in the picker.
+    pub fn name(&self) -> &str {
+        match self {
+            ThemeChoice::Light => "Light",
+            ThemeChoice::Dark => "Dark",
+            ThemeChoice::User { name, .. } => name,
+        }
+    }
+
+    /// Unique id, same as the name of the loaded `Theme`.
+    ///
+    /// User themes are prefixed with `user:`, so a user theme called "Dark"
+    /// or named after a syntect theme doesn't shadow the built-in one.
+    pub fn id(&self) -> String {
+        match self {
+            ThemeChoice::User { name, .. } => format!("{}{}", USER_THEME_PREFIX, name),
+            _ => self.name().to_string(),
+        }
+    }
+
+    /// Loads the theme files, registering a user highlight theme with the
+    /// shared highlighting engine.
+    ///
+    /// Fails with `DogmvError::InvalidTheme` if a file cannot be read or parsed.
+    /// An unknown highlight theme is reported here and replaced by the default.
+    pub fn load(&self) -> Result<Theme> {
+        let mut theme = self.load_with(|name, theme| {
+            HighlightEngine::update(|engine| {
+                engine.add_theme(name, theme);
+                Ok(())
+            })
+        })?;
+        theme.highlight_theme = HighlightEngine::global()
+            .resolve_theme(&theme.highlight_theme)
+            .to_string();
+        Ok(theme)
+    }
+
+    /// Loads the theme files, passing a user highlight theme to `register`.
+    fn load_with<F>(&self, register: F) -> Result<Theme>
+    where
+        F: FnOnce(&str, SyntectTheme) -> Result<()>,
+    {
+        let (css_path, highlight_path) = match self {
+            ThemeChoice::Light => return Ok(Theme::light()),
+            ThemeChoice::Dark => return Ok(Theme::dark()),
+            ThemeChoice::User { css, highlight, .. } => (css, highlight),
+        };
+        let id = self.id();
+
+        let mut dark = false;
+        let mut highlight_theme = DEFAULT_THEME.to_string();
+        if let Some(path) = highlight_path {
+            let theme = ThemeSet::get_theme(path)
+                .map_err(|e| DogmvError::InvalidTheme(path.clone(), e.to_string()))?;
+            dark = theme
+                .settings
+                .background
+                .is_some_and(|c| relative_luminance(c.r, c.g, c.b) < 0.5);
+            register(&id, theme)?;
+            highlight_theme = id.clone();
+        }
+
+        let css = match css_path {
+            Some(path) => load_css(path)?,
+            // A lone highlight theme keeps the built-in page matching its background
+            None => Theme::for_color_scheme(dark).css,
+        };
+
+        Ok(Theme {
+            name: id,
+            css,
+            highlight_theme,
+            dark,
+        })
+    }
+}
+
+/// Lists the themes for the picker: the built-in ones followed by the themes
+/// found in `dir` (`*.css` and `*.tmTheme`, grouped by file stem), by name.
+///
+/// A missing directory simply yields the built-in themes. Files are only
+/// checked when a theme is loaded.
+pub fn available_themes(dir: Option<&Path>) -> Vec<ThemeChoice> {
+    let mut user_themes: BTreeMap<String, (Option<PathBuf>, Option<PathBuf>)> = BTreeMap::new();
+
+    if let Some(entries) = dir.and_then(|dir| fs::read_dir(dir).ok()) {
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let (Some(stem), Some(extension)) = (
+                path.file_stem().and_then(|s| s.to_str()),
+                path.extension().and_then(|e| e.to_str()),
+            ) else {
+                continue;
+            };
+            let files = user_themes.entry(stem.to_string()).or_default();
+            match extension.to_lowercase().as_str() {
+                "css" => files.0 = Some(path.clone()),
+                "tmtheme" => files.1 = Some(path.clone()),
+                _ => {}
+            }
+        }
+    }
+
+    let mut choices = vec![ThemeChoice::Light, ThemeChoice::Dark];
+    choices.extend(
+        user_themes
+            .into_iter()
+            .filter(|(_, (css, highlight))| css.is_some() || highlight.is_some())
+            .map(|(name, (css, highlight))| ThemeChoice::User {
+                name,
+                css,
+                highlight,
+            }),
+    );
+    choices
+}
+
+/// Reads a user stylesheet and checks that it is at least structurally valid.
+fn load_css(path: &Path) -> Result<String> {
+    let css = fs::read_to_string(path)
+        .map_err(|e| DogmvError::InvalidTheme(path.to_path_buf(), e.to_string()))?;
+    validate_css(&css).map_err(|reason| DogmvError::InvalidTheme(path.to_path_buf(), reason))?;
+    Ok(css)
+}
+
+/// Checks that `css` is not empty and that its braces are balanced.
+///
+/// WebKit silently drops broken rules, so this catches the most common
+/// mistakes (a truncated file, a missing `}`) before the page looks wrong.
+fn validate_css(css: &str) -> std::result::Result<(), String> {
+    if css.trim().is_empty() {
+        return Err("the stylesheet is empty".to_string());
+    }
+
+    let mut open_braces = Vec::new();
+    let mut line = 1;
+    let mut chars = css.chars().peekable();
+    let mut in_string: Option<char> = None;
+
+    while let Some(ch) = chars.next() {
+        if ch == '\n' {
+            line += 1;
+        }
+        if let Some(quote) = in_string {
+            if ch == '\\' {
+                chars.next();
+            } else if ch == quote {
+                in_string = None;
+            }
+            continue;
+        }
+        match ch {
+            '"' | '\'' => in_string = Some(ch),
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for ch in chars.by_ref() {
+                    if ch == '\n' {
+                        line += 1;
+                    }
+                    if previous == '*' && ch == '/' {
+                        break;
+                    }
+                    previous = ch;
+                }
+            }
+            '{' => open_braces.push(line),
+            '}' if open_braces.pop().is_none() => {
+                return Err(format!("unexpected '}}' on line {}", line));
+            }
+            _ => {}
+        }
+    }
+
+    match open_braces.pop() {
+        Some(opened) => Err(format!("'{{' on line {} is never closed", opened)),
+        None => Ok(()),
+    }
+}
+
+/// Relative luminance (0.0 black to 1.0 white) of an sRGB color.
+fn relative_luminance(r: u8, g: u8, b: u8) -> f64 {
+    let channel = |c: u8| {
+        let c = f64::from(c) / 255.0;
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * channel(r) + 0.7152 * channel(g) + 0.0722 * channel(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_themes_pair_css_and_highlighting() {
+        let engine = HighlightEngine::global();
+        for theme in [Theme::light(), Theme::dark()] {
+            assert!(theme.css.contains("body"));
+            // The paired syntect theme must exist, otherwise code blocks fall back
+            assert!(engine.has_theme(&theme.highlight_theme));
+        }
+        assert!(Theme::for_color_scheme(true).dark);
+        assert!(!Theme::for_color_scheme(false).dark);
+    }
+
+    fn temp_themes_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dogmv-themes-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_available_themes_groups_files_by_stem() {
+        let dir = temp_themes_dir("list");
+        fs::write(dir.join("house.css"), "body { color: red; }").unwrap();
+        fs::write(dir.join("house.tmTheme"), "").unwrap();
+        fs::write(dir.join("night.tmTheme"), "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let names: Vec<String> = available_themes(Some(&dir))
+            .iter()
+            .map(|choice| choice.name().to_string())
+            .collect();
+        assert_eq!(names, vec!["Light", "Dark", "house", "night"]);
+
+        // User themes never share an id with the built-in ones
+        fs::write(dir.join("Dark.css"), "body { color: white; }").unwrap();
+        let ids: Vec<String> = available_themes(Some(&dir)).iter().map(ThemeChoice::id).collect();
+        assert_eq!(ids, vec!["Light", "Dark", "user:Dark", "user:house", "user:night"]);
+
+        // A missing directory only offers the built-in themes
+        assert_eq!(available_themes(Some(&dir.join("missing"))).len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_user_theme_replaces_stylesheet_and_highlighting() {
+        let dir = temp_themes_dir("load");
+        let css = dir.join("house-test.css");
+        let highlight = dir.join("house-test.tmTheme");
+        fs::write(&css, "body { font-family: serif; }").unwrap();
+        fs::write(
+            &highlight,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>name</key>
+    <string>House</string>
+    <key>settings</key>
+    <array>
+        <dict>
+            <key>settings</key>
+            <dict>
+                <key>background</key>
+                <string>#101010</string>
+                <key>foreground</key>
+                <string>#eeeeee</string>
+            </dict>
+        </dict>
+    </array>
+</dict>
+</plist>
+"#,
+        )
+        .unwrap();
+
+        let choice = ThemeChoice::User {
+            name: "house-test".to_string(),
+            css: Some(css),
+            highlight: Some(highlight),
+        };
+        let mut registered = None;
+        let theme = choice
+            .load_with(|name, theme| {
+                registered = Some((name.to_string(), theme));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(theme.css, "body { font-family: serif; }");
+        assert_eq!(theme.name, "user:house-test");
+        assert_eq!(theme.highlight_theme, "user:house-test");
+        assert!(theme.dark);
+        assert!(matches!(registered, Some((ref name, _)) if name == "user:house-test"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_theme_files_are_reported() {
+        let dir = temp_themes_dir("invalid");
+        let css = dir.join("broken.css");
+        let highlight = dir.join("broken.tmTheme");
+        fs::write(&css, "body {\n  color: red;\n").unwrap();
+        fs::write(&highlight, "not a plist").unwrap();
+
+        let css_only = ThemeChoice::User {
+            name: "broken".to_string(),
+            css: Some(css.clone()),
+            highlight: None,
+        };
+        match css_only.load() {
+            Err(DogmvError::InvalidTheme(path, reason)) => {
+                assert_eq!(path, css);
+                assert!(reason.contains("line 1"), "{}", reason);
+            }
+            other => panic!("expected an invalid theme error, got {:?}", other),
+        }
+
+        let highlight_only = ThemeChoice::User {
+            name: "broken".to_string(),
+            css: None,
+            highlight: Some(highlight.clone()),
+        };
+        assert!(matches!(
+            highlight_only.load(),
+            Err(DogmvError::InvalidTheme(path, _)) if path == highlight
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_css() {
+        assert!(validate_css("a { color: red; }\n@media print { a { color: black; } }").is_ok());
+        // Braces inside comments and strings don't count
+        assert!(validate_css("/* } */ a::after { content: \"{\"; }").is_ok());
+        assert!(validate_css("  \n").is_err());
+        assert_eq!(
+            validate_css("a { color: red; }\n}"),
+            Err("unexpected '}' on line 2".to_string())
+        );
+    }
+}
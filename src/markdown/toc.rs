@@ -0,0 +1,257 @@
+use crate::markdown::front_matter::{FieldValue, FrontMatter};
+use crate::markdown::outline::{heading_anchors, plain_text};
+use crate::markdown::renderer::escape_html;
+use comrak::nodes::{Ast, AstNode, NodeHtmlBlock, NodeValue};
+use comrak::Arena;
+use std::cell::RefCell;
+
+/// Front matter key for the shallowest heading level listed in a table of contents
+const MIN_DEPTH_KEY: &str = "toc_min_depth";
+
+/// Front matter key for the deepest heading level listed in a table of contents
+const MAX_DEPTH_KEY: &str = "toc_max_depth";
+
+/// Heading levels listed in a table of contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TocSettings {
+    pub min_depth: u8,
+    pub max_depth: u8,
+}
+
+impl Default for TocSettings {
+    fn default() -> Self {
+        Self {
+            min_depth: 1,
+            max_depth: 6,
+        }
+    }
+}
+
+impl TocSettings {
+    /// Reads `toc_min_depth` and `toc_max_depth` from the document's front matter.
+    ///
+    /// Missing or invalid values keep their defaults (all levels).
+    pub fn from_front_matter(front_matter: Option<&FrontMatter>) -> Self {
+        let depth = |key| match front_matter?.get(key)? {
+            FieldValue::Text(text) => text
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|d| (1..=6).contains(d)),
+            FieldValue::List(_) => None,
+        };
+        let defaults = Self::default();
+        Self {
+            min_depth: depth(MIN_DEPTH_KEY).unwrap_or(defaults.min_depth),
+            max_depth: depth(MAX_DEPTH_KEY).unwrap_or(defaults.max_depth),
+        }
+    }
+
+    fn includes(&self, level: u8) -> bool {
+        (self.min_depth..=self.max_depth).contains(&level)
+    }
+}
+
+/// The `[TOC]` and `<!-- toc -->` markers of a parsed document.
+pub struct TocMarkers<'a> {
+    markers: Vec<&'a AstNode<'a>>,
+    settings: TocSettings,
+}
+
+impl<'a> TocMarkers<'a> {
+    /// Finds the table of contents markers in the parsed document.
+    ///
+    /// `[TOC]` has to be a paragraph of its own. A list generated by another
+    /// tool between `<!-- toc -->` and `<!-- tocstop -->` is dropped, so it is
+    /// replaced rather than shown twice. This has to happen before the
+    /// document's raw HTML is sanitized.
+    pub fn find(root: &'a AstNode<'a>, settings: TocSettings) -> Self {
+        let markers: Vec<_> = root.descendants().filter(|node| is_marker(node)).collect();
+        for marker in &markers {
+            remove_stale_toc(marker);
+        }
+        Self { markers, settings }
+    }
+
+    /// Returns `true` if the document has no table of contents.
+    pub fn is_empty(&self) -> bool {
+        self.markers.is_empty()
+    }
+
+    /// Replaces the markers with a nested list of links to the document's headings.
+    ///
+    /// Links point to the ids comrak gives headings with `header_ids` set to
+    /// `prefix`, so this has to run after all other changes to the headings.
+    pub fn render(self, arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>, prefix: &str) {
+        if self.markers.is_empty() {
+            return;
+        }
+
+        let entries: Vec<_> = heading_anchors(root, prefix)
+            .into_iter()
+            .filter_map(|(node, anchor)| match node.data.borrow().value {
+                NodeValue::Heading(ref heading) => Some((heading.level, plain_text(node), anchor)),
+                _ => None,
+            })
+            .filter(|(level, _, _)| self.settings.includes(*level))
+            .collect();
+        let html = toc_html(&entries);
+
+        for marker in self.markers {
+            let start = marker.data.borrow().sourcepos.start;
+            let toc = arena.alloc(AstNode::new(RefCell::new(Ast::new(
+                NodeValue::HtmlBlock(NodeHtmlBlock {
+                    block_type: 0,
+                    literal: html.clone(),
+                }),
+                start,
+            ))));
+            marker.insert_before(toc);
+            marker.detach();
+        }
+    }
+}
+
+fn is_marker<'a>(node: &'a AstNode<'a>) -> bool {
+    match node.data.borrow().value {
+        NodeValue::Paragraph => {
+            node.children()
+                .all(|inline| matches!(inline.data.borrow().value, NodeValue::Text(_)))
+                && plain_text(node).eq_ignore_ascii_case("[toc]")
+        }
+        NodeValue::HtmlBlock(ref block) => comment_text(&block.literal).as_deref() == Some("toc"),
+        _ => false,
+    }
+}
+
+/// Returns the lowercased text of an HTML block that is a single comment.
+fn comment_text(literal: &str) -> Option<String> {
+    let text = literal.trim().strip_prefix("<!--")?.strip_suffix("-->")?;
+    Some(text.trim().to_lowercase())
+}
+
+/// Removes the blocks between a `<!-- toc -->` marker and its `<!-- tocstop -->`.
+fn remove_stale_toc<'a>(marker: &'a AstNode<'a>) {
+    let stop = marker.following_siblings().skip(1).find(|node| {
+        matches!(node.data.borrow().value,
+            NodeValue::HtmlBlock(ref block) if comment_text(&block.literal).as_deref() == Some("tocstop"))
+    });
+    let Some(stop) = stop else {
+        return;
+    };
+    while let Some(next) = marker.next_sibling() {
+        let done = next.same_node(stop);
+        next.detach();
+        if done {
+            break;
+        }
+    }
+}
+
+/// Builds the nested list for `(level, text, anchor)` entries.
+fn toc_html(entries: &[(u8, String, String)]) -> String {
+    let mut html = String::from("<nav class=\"toc\">\n");
+    // Levels of the lists currently open
+    let mut open: Vec<u8> = Vec::new();
+
+    for (level, text, anchor) in entries {
+        let level = *level;
+        match open.last().copied() {
+            None => {
+                html.push_str("<ul>\n");
+                open.push(level);
+            }
+            Some(top) if level > top => {
+                html.push_str("\n<ul>\n");
+                open.push(level);
+            }
+            Some(_) => {
+                html.push_str("</li>\n");
+                // Close deeper lists, unless the heading still belongs inside them
+                while open.len() > 1
+                    && level < open[open.len() - 1]
+                    && level <= open[open.len() - 2]
+                {
+                    html.push_str("</ul>\n</li>\n");
+                    open.pop();
+                }
+                if let Some(top) = open.last_mut() {
+                    *top = (*top).min(level);
+                }
+            }
+        }
+        html.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            escape_html(anchor),
+            escape_html(text)
+        ));
+    }
+
+    if !open.is_empty() {
+        html.push_str("</li>\n");
+        for _ in 1..open.len() {
+            html.push_str("</ul>\n</li>\n");
+        }
+        html.push_str("</ul>\n");
+    }
+    html.push_str("</nav>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(level: u8, text: &str) -> (u8, String, String) {
+        (level, text.to_string(), text.to_lowercase())
+    }
+
+    #[test]
+    fn test_toc_html_nesting() {
+        let html = toc_html(&[
+            entry(1, "A"),
+            entry(2, "B"),
+            entry(3, "C"),
+            entry(2, "D"),
+            entry(1, "E"),
+        ]);
+        assert_eq!(
+            html,
+            "<nav class=\"toc\">\n<ul>\n\
+             <li><a href=\"#a\">A</a>\n<ul>\n\
+             <li><a href=\"#b\">B</a>\n<ul>\n\
+             <li><a href=\"#c\">C</a></li>\n</ul>\n</li>\n\
+             <li><a href=\"#d\">D</a></li>\n</ul>\n</li>\n\
+             <li><a href=\"#e\">E</a></li>\n</ul>\n</nav>\n"
+        );
+    }
+
+    #[test]
+    fn test_toc_html_skipped_levels() {
+        // A level 2 heading after a level 3 one, below a level 1 heading
+        let html = toc_html(&[entry(1, "A"), entry(3, "B"), entry(2, "C")]);
+        assert_eq!(
+            html,
+            "<nav class=\"toc\">\n<ul>\n\
+             <li><a href=\"#a\">A</a>\n<ul>\n\
+             <li><a href=\"#b\">B</a></li>\n\
+             <li><a href=\"#c\">C</a></li>\n</ul>\n</li>\n</ul>\n</nav>\n"
+        );
+        // No headings at all
+        assert_eq!(toc_html(&[]), "<nav class=\"toc\">\n</nav>\n");
+    }
+
+    #[test]
+    fn test_settings_from_front_matter() {
+        let front_matter =
+            FrontMatter::parse("---\ntoc_min_depth: 2\ntoc_max_depth: 9\n---\n").unwrap();
+        assert_eq!(
+            TocSettings::from_front_matter(Some(&front_matter)),
+            TocSettings {
+                min_depth: 2,
+                max_depth: 6
+            }
+        );
+        assert_eq!(TocSettings::from_front_matter(None), TocSettings::default());
+    }
+}
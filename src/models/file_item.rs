@@ -1,4 +1,5 @@
-use gtk4::glib;
+use crate::markdown::{is_markdown_file, read_title, MarkdownOptions};
+use gtk4::{gio, glib};
 use gtk4::prelude::*;
 use gtk4::subclass::prelude::*;
 use glib::Properties;
@@ -21,6 +22,10 @@ mod file_item_priv {
         is_dir: RefCell<bool>,
         #[property(get, set)]
         is_symlink: RefCell<bool>,
+        /// Front matter title of a Markdown file, empty if it has none or
+        /// it hasn't been read yet (see `load_title`)
+        #[property(get, set)]
+        title: RefCell<String>,
     }
 
     #[glib::object_subclass]
@@ -56,6 +61,32 @@ impl FileItem {
             .build())
     }
 
+    /// Reads the front matter title of a Markdown file in the background and
+    /// sets the `title` property once it is known.
+    ///
+    /// Call again after the file changed to pick up a new title.
+    pub fn load_title(&self) {
+        let path = self.path_buf();
+        if self.is_dir() || self.is_symlink() || !is_markdown_file(&path) {
+            return;
+        }
+        let Some(delimiter) = MarkdownOptions::global().extension.front_matter_delimiter.clone()
+        else {
+            return;
+        };
+
+        let handle = gio::spawn_blocking(move || read_title(&path, &delimiter));
+        let item = self.downgrade();
+        glib::spawn_future_local(async move {
+            if let (Ok(title), Some(item)) = (handle.await, item.upgrade()) {
+                let title = title.unwrap_or_default();
+                if item.title() != title {
+                    item.set_title(title);
+                }
+            }
+        });
+    }
+
     /// Returns the path as a PathBuf.
     pub fn path_buf(&self) -> PathBuf {
         PathBuf::from(self.path())
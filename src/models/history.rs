@@ -0,0 +1,164 @@
+use std::path::PathBuf;
+
+/// Number of entries kept; the oldest ones are dropped first
+const HISTORY_CAPACITY: usize = 100;
+
+/// A document visited in the preview.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub path: PathBuf,
+    /// Anchor the document was opened at, if any
+    pub fragment: Option<String>,
+    /// Scroll offset when the document was left, to return to the same place
+    pub scroll: Option<f64>,
+}
+
+impl HistoryEntry {
+    pub fn new(path: PathBuf, fragment: Option<String>) -> Self {
+        Self {
+            path,
+            fragment,
+            scroll: None,
+        }
+    }
+}
+
+/// Browser-style back/forward history of the documents shown.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    // Index of the entry shown; `None` until the first push
+    current: Option<usize>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `entry` after the current one, dropping all forward entries.
+    ///
+    /// Visiting the current entry again (same path and anchor) is not recorded.
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if let Some(current) = self.current() {
+            if current.path == entry.path && current.fragment == entry.fragment {
+                return;
+            }
+        }
+
+        let next = self.current.map_or(0, |current| current + 1);
+        self.entries.truncate(next);
+        self.entries.push(entry);
+        if self.entries.len() > HISTORY_CAPACITY {
+            self.entries.remove(0);
+        }
+        self.current = Some(self.entries.len() - 1);
+    }
+
+    /// Returns the entry shown, if any.
+    pub fn current(&self) -> Option<&HistoryEntry> {
+        self.entries.get(self.current?)
+    }
+
+    /// Records the scroll offset of the entry shown before it is left.
+    pub fn set_scroll(&mut self, y: f64) {
+        if let Some(current) = self.current {
+            self.entries[current].scroll = Some(y);
+        }
+    }
+
+    pub fn can_go_back(&self) -> bool {
+        self.current.is_some_and(|current| current > 0)
+    }
+
+    pub fn can_go_forward(&self) -> bool {
+        self.current
+            .is_some_and(|current| current + 1 < self.entries.len())
+    }
+
+    /// Moves to the previous entry and returns it.
+    pub fn back(&mut self) -> Option<HistoryEntry> {
+        if !self.can_go_back() {
+            return None;
+        }
+        self.current = self.current.map(|current| current - 1);
+        self.current().cloned()
+    }
+
+    /// Moves to the next entry and returns it.
+    pub fn forward(&mut self) -> Option<HistoryEntry> {
+        if !self.can_go_forward() {
+            return None;
+        }
+        self.current = self.current.map(|current| current + 1);
+        self.current().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, fragment: Option<&str>) -> HistoryEntry {
+        HistoryEntry::new(PathBuf::from(path), fragment.map(str::to_string))
+    }
+
+    #[test]
+    fn test_back_and_forward() {
+        let mut history = History::new();
+        assert!(!history.can_go_back());
+        assert_eq!(history.back(), None);
+
+        history.push(entry("a.md", None));
+        history.set_scroll(120.0);
+        history.push(entry("b.md", Some("setup")));
+        history.push(entry("c.md", None));
+        assert!(history.can_go_back());
+        assert!(!history.can_go_forward());
+
+        assert_eq!(history.back().unwrap().path, PathBuf::from("b.md"));
+        let first = history.back().unwrap();
+        assert_eq!(first.path, PathBuf::from("a.md"));
+        assert_eq!(first.scroll, Some(120.0));
+        assert_eq!(history.back(), None);
+
+        let next = history.forward().unwrap();
+        assert_eq!(next.fragment.as_deref(), Some("setup"));
+    }
+
+    #[test]
+    fn test_push_drops_forward_entries() {
+        let mut history = History::new();
+        history.push(entry("a.md", None));
+        history.push(entry("b.md", None));
+        history.back();
+        history.push(entry("c.md", None));
+        assert!(!history.can_go_forward());
+        assert_eq!(history.back().unwrap().path, PathBuf::from("a.md"));
+    }
+
+    #[test]
+    fn test_repeated_visit_is_not_recorded() {
+        let mut history = History::new();
+        history.push(entry("a.md", None));
+        history.push(entry("a.md", None));
+        assert!(!history.can_go_back());
+        // A different anchor in the same document is a new entry
+        history.push(entry("a.md", Some("usage")));
+        assert!(history.can_go_back());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut history = History::new();
+        for i in 0..HISTORY_CAPACITY + 10 {
+            history.push(entry(&format!("{}.md", i), None));
+        }
+        let mut steps = 0;
+        while history.back().is_some() {
+            steps += 1;
+        }
+        assert_eq!(steps, HISTORY_CAPACITY - 1);
+        assert_eq!(history.current().unwrap().path, PathBuf::from("10.md"));
+    }
+}
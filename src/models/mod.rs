@@ -1,3 +1,5 @@
 pub mod file_item;
+pub mod history;
 
 pub use file_item::FileItem;
+pub use history::{History, HistoryEntry};
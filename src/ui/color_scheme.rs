@@ -0,0 +1,153 @@
+use gtk4::prelude::*;
+use gtk4::{gio, glib};
+use log::{info, warn};
+use std::cell::Cell;
+use std::rc::Rc;
+
+const PORTAL_BUS_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_OBJECT_PATH: &str = "/org/freedesktop/portal/desktop";
+const PORTAL_SETTINGS_INTERFACE: &str = "org.freedesktop.portal.Settings";
+const APPEARANCE_NAMESPACE: &str = "org.freedesktop.appearance";
+const COLOR_SCHEME_KEY: &str = "color-scheme";
+
+/// How long to wait for the settings portal before falling back to GTK settings
+const PORTAL_TIMEOUT_MS: i32 = 500;
+
+thread_local! {
+    /// Last color scheme reported by the settings portal; `None` without a
+    /// portal, before it has answered, or if it has no preference
+    static PORTAL_PREFERS_DARK: Cell<Option<bool>> = const { Cell::new(None) };
+}
+
+/// Returns `true` if the desktop asks applications to use a dark color scheme.
+///
+/// The freedesktop settings portal decides first (this is what GNOME and KDE
+/// set). Without a portal, or if it has no preference, the GTK settings
+/// decide: `gtk-application-prefer-dark-theme` or a `*-dark` GTK theme.
+///
+/// This never blocks: the portal value is the one cached by `watch_color_scheme`.
+pub fn prefers_dark() -> bool {
+    PORTAL_PREFERS_DARK.get().unwrap_or_else(gtk_prefers_dark)
+}
+
+/// Calls `callback` with the new preference whenever the desktop color scheme changes.
+///
+/// The settings portal is read once, asynchronously; `callback` is called if
+/// its answer changes the preference. After that the cached value is only
+/// updated from the portal's `SettingChanged` signal.
+///
+/// The returned subscription to the settings portal must be kept alive for as
+/// long as changes should be reported.
+pub fn watch_color_scheme<F>(callback: F) -> Option<gio::SignalSubscription>
+where
+    F: Fn(bool) + 'static,
+{
+    let callback: Rc<dyn Fn(bool)> = Rc::new(callback);
+
+    if let Some(settings) = gtk4::Settings::default() {
+        let notify = Rc::clone(&callback);
+        settings.connect_gtk_application_prefer_dark_theme_notify(move |_| notify(prefers_dark()));
+        let notify = Rc::clone(&callback);
+        settings.connect_gtk_theme_name_notify(move |_| notify(prefers_dark()));
+    }
+
+    let connection = gio::bus_get_sync(gio::BusType::Session, gio::Cancellable::NONE)
+        .map_err(|e| warn!("No session bus, color scheme changes are not followed: {}", e))
+        .ok()?;
+
+    // A change signalled before the initial read is answered is newer than the answer
+    let changed = Rc::new(Cell::new(false));
+
+    let subscription = {
+        let callback = Rc::clone(&callback);
+        let changed = Rc::clone(&changed);
+        connection.subscribe_to_signal(
+            Some(PORTAL_BUS_NAME),
+            Some(PORTAL_SETTINGS_INTERFACE),
+            Some("SettingChanged"),
+            Some(PORTAL_OBJECT_PATH),
+            Some(APPEARANCE_NAMESPACE),
+            gio::DBusSignalFlags::NONE,
+            move |signal| {
+                // SettingChanged(namespace: s, key: s, value: v)
+                let parameters = signal.parameters;
+                if parameters.n_children() != 3
+                    || parameters.child_value(1).str() != Some(COLOR_SCHEME_KEY)
+                {
+                    return;
+                }
+                changed.set(true);
+                PORTAL_PREFERS_DARK.set(color_scheme_is_dark(&parameters.child_value(2)));
+                let dark = prefers_dark();
+                info!("Desktop color scheme changed (dark: {})", dark);
+                callback(dark);
+            },
+        )
+    };
+
+    connection.call(
+        Some(PORTAL_BUS_NAME),
+        PORTAL_OBJECT_PATH,
+        PORTAL_SETTINGS_INTERFACE,
+        "Read",
+        Some(&(APPEARANCE_NAMESPACE, COLOR_SCHEME_KEY).to_variant()),
+        None,
+        gio::DBusCallFlags::NONE,
+        PORTAL_TIMEOUT_MS,
+        gio::Cancellable::NONE,
+        move |reply| {
+            let reply = match reply {
+                Ok(reply) => reply,
+                Err(e) => {
+                    info!("Settings portal not available: {}", e);
+                    return;
+                }
+            };
+            if changed.get() {
+                return;
+            }
+            let before = prefers_dark();
+            PORTAL_PREFERS_DARK.set(color_scheme_is_dark(&reply));
+            let dark = prefers_dark();
+            if dark != before {
+                info!("Settings portal color scheme read (dark: {})", dark);
+                callback(dark);
+            }
+        },
+    );
+
+    Some(subscription)
+}
+
+/// Interprets a portal color scheme value: 1 is dark, 2 is light, 0 is no preference.
+///
+/// `Read` wraps the value in one or more variants (and the reply in a tuple),
+/// so containers are unwrapped until the number is found.
+fn color_scheme_is_dark(value: &glib::Variant) -> Option<bool> {
+    if let Some(scheme) = value.get::<u32>() {
+        return match scheme {
+            1 => Some(true),
+            2 => Some(false),
+            _ => None,
+        };
+    }
+    if let Some(inner) = value.as_variant() {
+        return color_scheme_is_dark(&inner);
+    }
+    if value.is_container() && value.n_children() == 1 {
+        return color_scheme_is_dark(&value.child_value(0));
+    }
+    None
+}
+
+/// Falls back to the GTK settings when the portal has nothing to say.
+fn gtk_prefers_dark() -> bool {
+    gtk4::Settings::default()
+        .map(|settings| {
+            settings.is_gtk_application_prefer_dark_theme()
+                || settings
+                    .gtk_theme_name()
+                    .is_some_and(|name| name.to_lowercase().ends_with("-dark"))
+        })
+        .unwrap_or(false)
+}
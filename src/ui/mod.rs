@@ -1,7 +1,19 @@
+pub mod color_scheme;
+pub mod navigation;
+pub mod outline;
 pub mod preview;
 pub mod sidebar;
+pub mod tabs;
 pub mod tree_view;
 
-pub use preview::{display_markdown, display_welcome_message};
+pub use color_scheme::{prefers_dark, watch_color_scheme};
+pub use navigation::setup_link_navigation;
+pub use outline::{create_outline_view, setup_outline_handlers};
+pub use preview::{
+    display_error_message, display_markdown, display_welcome_message, patch_markdown,
+    query_scroll_position, rerender_markdown, setup_asset_scheme, setup_scroll_restore, Preview,
+    ReloadMode, ScrollTarget,
+};
 pub use sidebar::{setup_toggle_button, setup_toggle_button_css};
-pub use tree_view::{create_tree_view, setup_file_selection_handler};
+pub use tabs::{SyncScroll, Tabs};
+pub use tree_view::{create_tree_view, setup_file_selection_handler, setup_tree_sync};
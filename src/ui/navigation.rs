@@ -0,0 +1,357 @@
+use crate::app_state::{same_file, AppState};
+use crate::markdown::is_supported_file;
+use gtk4::{gdk, gio, glib};
+use log::{info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use webkit6::prelude::*;
+use webkit6::{NavigationPolicyDecision, NavigationType, PolicyDecisionType};
+
+/// URI schemes handed to the desktop instead of being loaded in the preview
+const EXTERNAL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Opens a URI in another application; `launch_default_uri` outside of tests
+pub type UriLauncher = Rc<dyn Fn(&str) -> Result<(), glib::Error>>;
+
+/// Where a link clicked in the preview leads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkTarget {
+    /// An anchor in the document shown
+    Fragment(String),
+    /// A local file dogmv can display, optionally with an anchor
+    Document {
+        path: PathBuf,
+        fragment: Option<String>,
+    },
+    /// A web or mail link
+    External(String),
+    /// Anything else
+    Other,
+}
+
+/// Works out where the link to `uri` leads from the document `current_file`.
+///
+/// Relative links have already been resolved against the page's base (the
+/// directory of the document) by the WebView, so `#anchor` links arrive as
+/// `file:///dir/#anchor`.
+pub fn resolve_link(uri: &str, current_file: Option<&Path>) -> LinkTarget {
+    let scheme = uri.split_once(':').map(|(scheme, _)| scheme.to_ascii_lowercase());
+    if scheme.is_some_and(|scheme| EXTERNAL_SCHEMES.contains(&scheme.as_str())) {
+        return LinkTarget::External(uri.to_string());
+    }
+
+    let (location, fragment) = match uri.split_once('#') {
+        Some((location, fragment)) => (location, Some(fragment)),
+        None => (uri, None),
+    };
+    let Ok((path, _)) = glib::filename_from_uri(location) else {
+        return LinkTarget::Other;
+    };
+    let fragment = fragment.filter(|fragment| !fragment.is_empty()).map(|fragment| {
+        glib::Uri::unescape_string(fragment, None::<&str>)
+            .map(String::from)
+            .unwrap_or_else(|| fragment.to_string())
+    });
+
+    if let (Some(current), Some(fragment)) = (current_file, fragment.as_ref()) {
+        let current = fs::canonicalize(current).unwrap_or_else(|_| current.to_path_buf());
+        let same_document = same_file(&path, &current)
+            || (location.ends_with('/')
+                && current.parent().is_some_and(|dir| same_file(&path, dir)));
+        if same_document {
+            return LinkTarget::Fragment(fragment.clone());
+        }
+    }
+
+    // Local files dogmv can't display are not handed to other applications,
+    // so a document can't get executables or scripts next to it launched
+    if path.is_file() && is_supported_file(&path) {
+        LinkTarget::Document { path, fragment }
+    } else {
+        LinkTarget::Other
+    }
+}
+
+/// Opens `uri` with the desktop's default application for it.
+pub fn launch_default_uri(uri: &str) -> Result<(), glib::Error> {
+    let context = gdk::Display::default().map(|display| display.app_launch_context());
+    gio::AppInfo::launch_default_for_uri(uri, context.as_ref())
+}
+
+/// Hands `uri` to `launcher`, logging failures. Returns `true` on success.
+fn open_externally(uri: &str, launcher: &UriLauncher) -> bool {
+    info!("Opening link externally: {}", uri);
+    match launcher(uri) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Failed to open {}: {}", uri, e);
+            false
+        }
+    }
+}
+
+/// What the preview does about a navigation the WebView asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LinkAction {
+    /// Let the WebView go ahead, e.g. with pages loaded by dogmv itself
+    Load,
+    /// Stop the navigation, having done whatever it called for
+    Ignore,
+    /// Scroll the document shown to an anchor
+    OpenFragment(String),
+    /// Open a local file in the tab
+    OpenDocument {
+        path: PathBuf,
+        fragment: Option<String>,
+    },
+}
+
+/// Decides what to do about a navigation to `uri` from the document
+/// `current_file`, launching web and mail links the user clicked with
+/// `launcher`.
+fn link_action(
+    uri: &str,
+    current_file: Option<&Path>,
+    user_initiated: bool,
+    launcher: &UriLauncher,
+) -> LinkAction {
+    match resolve_link(uri, current_file) {
+        LinkTarget::Fragment(fragment) => LinkAction::OpenFragment(fragment),
+        LinkTarget::Document { path, fragment } => LinkAction::OpenDocument { path, fragment },
+        LinkTarget::External(uri) if user_initiated => {
+            open_externally(&uri, launcher);
+            LinkAction::Ignore
+        }
+        // Pages can't open applications by themselves (redirects, frames, scripts)
+        LinkTarget::External(uri) => {
+            warn!("Not opening {}, the user didn't click it", uri);
+            LinkAction::Ignore
+        }
+        LinkTarget::Other if user_initiated => {
+            info!("Not following link to {}", uri);
+            LinkAction::Ignore
+        }
+        LinkTarget::Other => LinkAction::Load,
+    }
+}
+
+/// Decides where links clicked in the preview lead.
+///
+/// Linked Markdown and source files are opened like files picked in the
+/// tree (moving the watcher and the tree selection), and `#anchor` links
+/// scroll the document shown. Web and mail links the user clicks are opened
+/// in the desktop's default application. The WebView itself never navigates
+/// away from the pages dogmv renders.
+pub fn setup_link_navigation(state: &AppState) {
+    setup_link_navigation_with(state, Rc::new(launch_default_uri));
+}
+
+/// Like `setup_link_navigation`, opening external links with `launcher`.
+pub fn setup_link_navigation_with(state: &AppState, launcher: UriLauncher) {
+    // The WebView keeps the handler alive, so it must not keep the tab alive
+    let state_weak = state.downgrade();
+
+    state.preview.webview.connect_decide_policy(move |_, decision, decision_type| {
+        if !matches!(
+            decision_type,
+            PolicyDecisionType::NavigationAction | PolicyDecisionType::NewWindowAction
+        ) {
+            return false;
+        }
+        let Some(navigation) = decision.downcast_ref::<NavigationPolicyDecision>() else {
+            return false;
+        };
+        let Some(mut action) = navigation.navigation_action() else {
+            return false;
+        };
+        let Some(uri) = action.request().and_then(|request| request.uri()) else {
+            return false;
+        };
+        // Loads started by dogmv itself (`load_html`) are not user navigations.
+        // dogmv keeps its own history; WebKit's would bring back stale pages.
+        let user_initiated = decision_type == PolicyDecisionType::NewWindowAction
+            || matches!(
+                action.navigation_type(),
+                NavigationType::LinkClicked
+                    | NavigationType::FormSubmitted
+                    | NavigationType::BackForward
+            );
+
+        let Some(state) = state_weak.upgrade() else {
+            return false;
+        };
+        let current_file = state.current_file.lock().ok().and_then(|f| f.clone());
+        let action = link_action(&uri, current_file.as_deref(), user_initiated, &launcher);
+        if action == LinkAction::Load {
+            return false;
+        }
+        decision.ignore();
+        match action {
+            LinkAction::OpenFragment(fragment) => state.open_fragment(fragment),
+            LinkAction::OpenDocument { path, fragment } => {
+                info!("Following link to {}", path.display());
+                state.open_link(&path, fragment);
+            }
+            LinkAction::Load | LinkAction::Ignore => {}
+        }
+        true
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(path: &Path, suffix: &str) -> String {
+        format!("{}{}", glib::filename_to_uri(path, None).unwrap(), suffix)
+    }
+
+    #[test]
+    fn test_resolve_link() {
+        let dir = std::env::temp_dir().join(format!("dogmv-links-{}", std::process::id()));
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap();
+        let readme = dir.join("README.md");
+        let setup = dir.join("docs/setup guide.md");
+        let image = dir.join("logo.png");
+        for file in [&readme, &setup, &image] {
+            fs::write(file, "").unwrap();
+        }
+        let current = Some(readme.as_path());
+
+        // `#anchor` resolves against the directory of the document
+        assert_eq!(
+            resolve_link(&uri(&dir, "/#usage"), current),
+            LinkTarget::Fragment("usage".to_string())
+        );
+        assert_eq!(
+            resolve_link(&uri(&readme, "#%E4%BD%BF%E3%81%84%E6%96%B9"), current),
+            LinkTarget::Fragment("使い方".to_string())
+        );
+        assert_eq!(
+            resolve_link(&uri(&setup, "#linux"), current),
+            LinkTarget::Document {
+                path: setup.clone(),
+                fragment: Some("linux".to_string())
+            }
+        );
+        assert_eq!(
+            resolve_link(&uri(&setup, ""), current),
+            LinkTarget::Document {
+                path: setup.clone(),
+                fragment: None
+            }
+        );
+        // Files dogmv can't display are not launched either
+        assert_eq!(resolve_link(&uri(&image, "#top"), current), LinkTarget::Other);
+        assert_eq!(resolve_link(&uri(&dir.join("missing.md"), ""), current), LinkTarget::Other);
+        assert_eq!(resolve_link(&uri(&dir, "/"), current), LinkTarget::Other);
+        assert_eq!(resolve_link("about:blank", current), LinkTarget::Other);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_web_and_mail_links_are_external() {
+        for link in [
+            "https://example.com/a.md#intro",
+            "HTTP://example.com",
+            "mailto:dev@example.com?subject=dogmv",
+        ] {
+            assert_eq!(resolve_link(link, None), LinkTarget::External(link.to_string()));
+        }
+        // Not opened, though WebKit runs `javascript:` links without asking;
+        // the renderer blanks them (see `sanitize_raw_html`)
+        assert_eq!(resolve_link("javascript:alert(1)", None), LinkTarget::Other);
+    }
+
+    #[test]
+    fn test_open_externally_uses_launcher() {
+        let opened = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let recorder = Rc::clone(&opened);
+        let launcher: UriLauncher = Rc::new(move |uri| {
+            recorder.borrow_mut().push(uri.to_string());
+            Ok(())
+        });
+        assert!(open_externally("https://example.com", &launcher));
+        assert_eq!(*opened.borrow(), ["https://example.com"]);
+
+        let failing: UriLauncher = Rc::new(|_| {
+            Err(glib::Error::new(gio::IOErrorEnum::NotSupported, "no handler"))
+        });
+        assert!(!open_externally("mailto:dev@example.com", &failing));
+    }
+
+    #[test]
+    fn test_link_action_launches_only_clicked_web_links() {
+        let dir = std::env::temp_dir().join(format!("dogmv-link-actions-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap();
+        let readme = dir.join("README.md");
+        let guide = dir.join("guide.md");
+        let program = dir.join("setup.exe");
+        for file in [&readme, &guide, &program] {
+            fs::write(file, "").unwrap();
+        }
+        let current = Some(readme.as_path());
+
+        let opened = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let recorder = Rc::clone(&opened);
+        let launcher: UriLauncher = Rc::new(move |uri| {
+            recorder.borrow_mut().push(uri.to_string());
+            Ok(())
+        });
+
+        assert_eq!(
+            link_action("https://example.com", current, true, &launcher),
+            LinkAction::Ignore
+        );
+        // Redirects and frames don't launch anything
+        assert_eq!(
+            link_action("https://example.com/redirected", current, false, &launcher),
+            LinkAction::Ignore
+        );
+        assert_eq!(
+            link_action(&uri(&program, ""), current, true, &launcher),
+            LinkAction::Ignore
+        );
+        assert_eq!(*opened.borrow(), ["https://example.com"]);
+
+        assert_eq!(
+            link_action(&uri(&guide, "#usage"), current, true, &launcher),
+            LinkAction::OpenDocument {
+                path: guide.clone(),
+                fragment: Some("usage".to_string())
+            }
+        );
+        assert_eq!(
+            link_action(&uri(&readme, "#usage"), current, true, &launcher),
+            LinkAction::OpenFragment("usage".to_string())
+        );
+        // The pages dogmv loads itself
+        assert_eq!(link_action(&uri(&dir, "/"), current, false, &launcher), LinkAction::Load);
+        assert_eq!(link_action("about:blank", current, false, &launcher), LinkAction::Load);
+        assert_eq!(opened.borrow().len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_closed_tab_webview_is_disposed() {
+        // Needs a display
+        if gtk4::init().is_err() {
+            return;
+        }
+        let preview = crate::ui::Preview::new();
+        let (watch_tx, _watch_rx) = async_channel::unbounded();
+        let state = AppState::new(&preview, Path::new("."), watch_tx, crate::ui::ReloadMode::Full);
+        setup_link_navigation(&state);
+
+        let webview = preview.webview.downgrade();
+        drop(preview);
+        state.close();
+        drop(state);
+        assert!(webview.upgrade().is_none());
+    }
+}
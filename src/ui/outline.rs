@@ -0,0 +1,110 @@
+use crate::markdown::Heading;
+use crate::ui::Tabs;
+use gtk4::prelude::*;
+use gtk4::{Box as GtkBox, Label, ListBox, ListBoxRow, Orientation, ScrolledWindow, SelectionMode};
+
+/// Indentation per heading level in the outline, in pixels
+const LEVEL_INDENT: i32 = 12;
+
+/// Creates the outline panel listing the headings of the current document
+pub fn create_outline_view() -> (GtkBox, ListBox) {
+    let title = Label::new(Some("アウトライン"));
+    title.set_xalign(0.0);
+    title.set_margin_start(6);
+    title.set_margin_top(6);
+    title.set_margin_bottom(3);
+    title.add_css_class("heading");
+
+    let list = ListBox::new();
+    list.set_selection_mode(SelectionMode::Single);
+    let placeholder = Label::new(Some("見出しがありません"));
+    placeholder.add_css_class("dim-label");
+    placeholder.set_margin_top(12);
+    list.set_placeholder(Some(&placeholder));
+
+    let scroll = ScrolledWindow::new();
+    scroll.set_vexpand(true);
+    scroll.set_hexpand(true);
+    scroll.set_child(Some(&list));
+
+    let outline_box = GtkBox::new(Orientation::Vertical, 0);
+    outline_box.append(&title);
+    outline_box.append(&scroll);
+    (outline_box, list)
+}
+
+/// Replaces the rows of the outline with `headings`
+fn set_headings(list: &ListBox, headings: &[Heading]) {
+    while let Some(row) = list.row_at_index(0) {
+        list.remove(&row);
+    }
+    for heading in headings {
+        let label = Label::new(Some(&heading.text));
+        label.set_xalign(0.0);
+        label.set_ellipsize(gtk4::pango::EllipsizeMode::End);
+        label.set_tooltip_text(Some(&heading.text));
+        label.set_margin_start(i32::from(heading.level.saturating_sub(1)) * LEVEL_INDENT + 6);
+        label.set_margin_end(6);
+        label.set_margin_top(3);
+        label.set_margin_bottom(3);
+
+        let row = ListBoxRow::new();
+        row.set_child(Some(&label));
+        list.append(&row);
+    }
+}
+
+/// Connects the outline panel to the preview tabs.
+///
+/// The outline follows the document shown in the current tab (including live
+/// reloads), clicking a heading scrolls the preview to it, and the section at
+/// the top of the preview is highlighted while scrolling.
+pub fn setup_outline_handlers(list: &ListBox, tabs: &Tabs) {
+    let list_weak = list.downgrade();
+    let tabs_clone = tabs.clone();
+    tabs.connect_tab_added(move |state| {
+        let list_weak_clone = list_weak.clone();
+        let tabs = tabs_clone.clone();
+        let tab = state.downgrade();
+        state.preview.connect_outline_changed(move |headings| {
+            if let (Some(list), Some(tab)) = (list_weak_clone.upgrade(), tab.upgrade()) {
+                if tabs.is_current(&tab) {
+                    set_headings(&list, headings);
+                }
+            }
+        });
+
+        let list_weak_clone = list_weak.clone();
+        let tabs = tabs_clone.clone();
+        let tab = state.downgrade();
+        state.preview.connect_current_heading(move |index| {
+            if let (Some(list), Some(tab)) = (list_weak_clone.upgrade(), tab.upgrade()) {
+                if tabs.is_current(&tab) {
+                    select_heading(&list, index);
+                }
+            }
+        });
+    });
+
+    let list_weak = list.downgrade();
+    tabs.connect_switched(move |state| {
+        if let Some(list) = list_weak.upgrade() {
+            set_headings(&list, &state.preview.headings());
+        }
+    });
+
+    let tabs = tabs.clone();
+    list.connect_row_activated(move |_, row| {
+        if let (Ok(index), Some(state)) = (usize::try_from(row.index()), tabs.current()) {
+            state.preview.scroll_to_heading(index);
+        }
+    });
+}
+
+/// Highlights the heading at `index`, or none
+fn select_heading(list: &ListBox, index: Option<usize>) {
+    match index.and_then(|index| list.row_at_index(index as i32)) {
+        Some(row) => list.select_row(Some(&row)),
+        None => list.unselect_all(),
+    }
+}
@@ -1,73 +1,715 @@
-use crate::markdown::{create_html, load_markdown, render_markdown, is_markdown_file, render_source_code};
-use log::{error, info};
-use std::path::Path;
+use crate::error::{DogmvError, Result};
+use crate::markdown::renderer::escape_html;
+use crate::markdown::{
+    asset_for_uri, create_html, load_markdown, render_diff_document, render_document,
+    with_cancel_token, DocumentKey, Heading, RenderCache, RenderedDocument, Theme, ASSET_SCHEME,
+};
+use gtk4::prelude::*;
+use gtk4::{gdk, gio, glib, Box as GtkBox, Button, Label, Orientation, Overlay, Spinner};
+use log::{error, info, warn};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
 use webkit6::prelude::*;
-use webkit6::WebView;
+use webkit6::{LoadEvent, UserContentManager, WebContext, WebView};
 
-/// Displays a file in the WebView (Markdown or source code)
-pub fn display_markdown(webview: &WebView, file_path: &Path) {
-    // Check if it's a Markdown file
-    if is_markdown_file(file_path) {
-        display_markdown_file(webview, file_path);
-    } else {
-        display_source_file(webview, file_path);
+/// How a file that is already shown is refreshed after it changes on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadMode {
+    /// Load a freshly generated document (resets all page state)
+    Full,
+    /// Send the new body to the loaded page and replace only the changed blocks
+    Patch,
+}
+
+impl ReloadMode {
+    /// Parses a mode name as used in `DOGMV_RELOAD_MODE` ("full" or "patch").
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "full" => Some(ReloadMode::Full),
+            "patch" => Some(ReloadMode::Patch),
+            _ => None,
+        }
     }
 }
 
-/// Displays a Markdown file in the WebView
-fn display_markdown_file(webview: &WebView, file_path: &Path) {
-    match load_markdown(file_path) {
-        Ok(markdown) => {
-            let html_body = render_markdown(&markdown);
+/// Where a page is scrolled to once it has loaded
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScrollTarget {
+    /// A vertical scroll offset in pixels
+    Offset(f64),
+    /// The element with this id (or name), as in a `#fragment` link
+    Fragment(String),
+}
 
-            // Get base directory for relative paths
-            let base_dir = file_path
-                .parent()
-                .and_then(|p| p.to_str())
-                .unwrap_or("");
+impl ScrollTarget {
+    /// Returns the JavaScript that scrolls the page to the target.
+    fn script(&self) -> String {
+        match self {
+            ScrollTarget::Offset(y) => format!("window.scrollTo(0, {});", y),
+            ScrollTarget::Fragment(id) => format!(
+                "(function (id) {{ var el = document.getElementById(id) || document.getElementsByName(id)[0]; if (el) {{ el.scrollIntoView(); }} }})({});",
+                js_string_literal(id)
+            ),
+        }
+    }
+}
 
-            let full_html = create_html(&html_body, base_dir);
-            webview.load_html(&full_html, None);
-            info!("Markdown displayed successfully");
+/// Delay before the loading indicator appears, so fast renders don't flicker
+const SPINNER_DELAY: Duration = Duration::from_millis(150);
+
+/// Number of rendered documents kept for instant switching
+const RENDER_CACHE_CAPACITY: usize = 32;
+
+/// Script message handler through which pages report the current section
+const OUTLINE_MESSAGE_HANDLER: &str = "dogmvOutline";
+
+/// Script message handler through which pages report their scroll position
+const SCROLL_MESSAGE_HANDLER: &str = "dogmvScroll";
+
+/// The preview pane: a WebView with a loading indicator and an error banner on top of it.
+///
+/// Files are read and rendered on a worker thread. Starting a new render
+/// cancels the previous one, so clicking through the tree quickly never
+/// shows a stale document. Rendered documents are kept in a small LRU cache
+/// keyed by path, modification time and size.
+#[derive(Clone)]
+pub struct Preview {
+    pub webview: WebView,
+    overlay: Overlay,
+    spinner: Spinner,
+    // Errors that don't replace the document, e.g. a theme that failed to load
+    banner: GtkBox,
+    banner_title: Label,
+    banner_message: Label,
+    // Cancellation flag of the render in progress, if any
+    active_render: Arc<Mutex<Option<Arc<AtomicBool>>>>,
+    cache: Arc<Mutex<RenderCache>>,
+    theme: Arc<Mutex<Theme>>,
+    // File documents are compared with, if any
+    diff_base: Arc<Mutex<Option<PathBuf>>>,
+    // Source of the document currently shown, for re-rendering without disk access
+    shown_source: Arc<Mutex<Option<(PathBuf, String)>>>,
+    // Headings of the document currently shown
+    shown_headings: Rc<RefCell<Vec<Heading>>>,
+    // Called with the headings of every document shown
+    outline_listeners: Rc<RefCell<Vec<Box<dyn Fn(&[Heading])>>>>,
+}
+
+impl Preview {
+    pub fn new() -> Self {
+        info!("Creating WebView");
+        let content_manager = UserContentManager::new();
+        content_manager.register_script_message_handler(OUTLINE_MESSAGE_HANDLER, None);
+        content_manager.register_script_message_handler(SCROLL_MESSAGE_HANDLER, None);
+        let webview = WebView::builder()
+            .user_content_manager(&content_manager)
+            .build();
+        webview.set_hexpand(true);
+        webview.set_vexpand(true);
+
+        let spinner = Spinner::new();
+        spinner.set_halign(gtk4::Align::Center);
+        spinner.set_valign(gtk4::Align::Center);
+        spinner.set_size_request(48, 48);
+        spinner.set_visible(false);
+
+        let banner_title = Label::new(None);
+        banner_title.add_css_class("heading");
+        banner_title.set_xalign(0.0);
+        let banner_message = Label::new(None);
+        banner_message.set_wrap(true);
+        banner_message.set_xalign(0.0);
+        banner_message.set_selectable(true);
+        let text = GtkBox::new(Orientation::Vertical, 4);
+        text.append(&banner_title);
+        text.append(&banner_message);
+
+        let close_button = Button::from_icon_name("window-close-symbolic");
+        close_button.add_css_class("flat");
+        close_button.set_valign(gtk4::Align::Start);
+        close_button.set_tooltip_text(Some("閉じる"));
+
+        let banner = GtkBox::new(Orientation::Horizontal, 12);
+        banner.add_css_class("app-notification");
+        banner.add_css_class("osd");
+        banner.set_halign(gtk4::Align::Center);
+        banner.set_valign(gtk4::Align::Start);
+        banner.set_margin_top(12);
+        banner.set_margin_start(12);
+        banner.set_margin_end(12);
+        banner.append(&text);
+        banner.append(&close_button);
+        banner.set_visible(false);
+
+        let banner_weak = banner.downgrade();
+        close_button.connect_clicked(move |_| {
+            if let Some(banner) = banner_weak.upgrade() {
+                banner.set_visible(false);
+            }
+        });
+
+        let overlay = Overlay::new();
+        overlay.set_child(Some(&webview));
+        overlay.add_overlay(&spinner);
+        overlay.add_overlay(&banner);
+
+        Self {
+            webview,
+            overlay,
+            spinner,
+            banner,
+            banner_title,
+            banner_message,
+            active_render: Arc::new(Mutex::new(None)),
+            cache: Arc::new(Mutex::new(RenderCache::new(RENDER_CACHE_CAPACITY))),
+            theme: Arc::new(Mutex::new(Theme::default())),
+            diff_base: Arc::new(Mutex::new(None)),
+            shown_source: Arc::new(Mutex::new(None)),
+            shown_headings: Rc::new(RefCell::new(Vec::new())),
+            outline_listeners: Rc::new(RefCell::new(Vec::new())),
         }
-        Err(e) => {
-            error!("Failed to load markdown file '{}': {}", file_path.display(), e);
-            let error_html = create_error_html(
-                "Failed to Load File",
-                &format!("Could not read file: {}\n\nError: {}", file_path.display(), e)
-            );
-            webview.load_html(&error_html, None);
+    }
+
+    /// Returns the widget to pack into the window.
+    pub fn widget(&self) -> &Overlay {
+        &self.overlay
+    }
+
+    /// Shows an error above the document, which stays as it is, until the banner is closed.
+    pub fn show_error(&self, title: &str, message: &str) {
+        self.banner_title.set_text(title);
+        self.banner_message.set_text(message);
+        self.banner.set_visible(true);
+    }
+
+    /// Forgets every cached render of `file_path`, e.g. after it changed on disk.
+    pub fn invalidate(&self, file_path: &Path) {
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.invalidate(file_path);
+        }
+    }
+
+    /// Returns the theme documents are rendered with.
+    pub fn theme(&self) -> Theme {
+        self.theme.lock().map(|theme| theme.clone()).unwrap_or_default()
+    }
+
+    /// Switches the theme used for subsequent renders.
+    ///
+    /// The page currently shown is not touched; see `rerender_markdown`.
+    pub fn set_theme(&self, theme: Theme) {
+        info!("Switching preview theme to {}", theme.name);
+
+        // Avoid a white flash between pages of a dark theme
+        // (#0d1117, the body background of the dark stylesheet)
+        let background = if theme.dark {
+            gdk::RGBA::new(13.0 / 255.0, 17.0 / 255.0, 23.0 / 255.0, 1.0)
+        } else {
+            gdk::RGBA::WHITE
+        };
+        self.webview.set_background_color(&background);
+
+        if let Ok(mut current) = self.theme.lock() {
+            *current = theme;
+        }
+        // Cached bodies contain highlighted code of the old theme
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.clear();
+        }
+    }
+
+    /// Compares the documents rendered from now on with `base`, or stops comparing.
+    ///
+    /// While a base is set, Markdown documents are shown as a rendered diff
+    /// against it. Such renders bypass the cache.
+    pub fn set_diff_base(&self, base: Option<PathBuf>) {
+        if let Ok(mut current) = self.diff_base.lock() {
+            *current = base;
+        }
+    }
+
+    /// Returns the file documents are compared with, if any.
+    pub fn diff_base(&self) -> Option<PathBuf> {
+        self.diff_base.lock().ok().and_then(|base| base.clone())
+    }
+
+    /// Calls `f` with the headings of each document shown from now on.
+    ///
+    /// Documents that fail to load and source files have no headings.
+    pub fn connect_outline_changed<F: Fn(&[Heading]) + 'static>(&self, f: F) {
+        self.outline_listeners.borrow_mut().push(Box::new(f));
+    }
+
+    /// Returns the headings of the document currently shown.
+    pub fn headings(&self) -> Vec<Heading> {
+        self.shown_headings.borrow().clone()
+    }
+
+    /// Calls `f` with the index of the heading whose section is at the top of
+    /// the page whenever it changes while scrolling, or `None` above the first one.
+    pub fn connect_current_heading<F: Fn(Option<usize>) + 'static>(&self, f: F) {
+        let Some(content_manager) = self.webview.user_content_manager() else {
+            warn!("WebView has no content manager, the current section is not tracked");
+            return;
+        };
+        // Pages report the anchor of the heading, or its index without heading ids
+        let shown_headings = Rc::clone(&self.shown_headings);
+        content_manager.connect_script_message_received(
+            Some(OUTLINE_MESSAGE_HANDLER),
+            move |_, value| {
+                let index = if value.is_string() {
+                    let anchor = value.to_str();
+                    let headings = shown_headings.borrow();
+                    headings.iter().position(|heading| heading.anchor == anchor.as_str())
+                } else {
+                    usize::try_from(value.to_int32()).ok()
+                };
+                f(index)
+            },
+        );
+    }
+
+    /// Calls `f` with the position of the page whenever the user scrolls it.
+    ///
+    /// The position is opaque JSON to be passed to `sync_scroll` of another preview.
+    pub fn connect_scrolled<F: Fn(&str) + 'static>(&self, f: F) {
+        let Some(content_manager) = self.webview.user_content_manager() else {
+            warn!("WebView has no content manager, scrolling is not reported");
+            return;
+        };
+        content_manager.connect_script_message_received(
+            Some(SCROLL_MESSAGE_HANDLER),
+            move |_, value| f(&value.to_str()),
+        );
+    }
+
+    /// Scrolls the page to a `position` reported by another preview.
+    ///
+    /// With `by_anchor` the page is aligned on the heading with the same
+    /// anchor if it has one; otherwise the relative position is matched.
+    pub fn sync_scroll(&self, position: &str, by_anchor: bool) {
+        self.webview.evaluate_javascript(
+            &sync_scroll_script(position, by_anchor),
+            None,
+            None,
+            gio::Cancellable::NONE,
+            |_| {},
+        );
+    }
+
+    /// Scrolls the page to the heading at `index` in the document's outline.
+    pub fn scroll_to_heading(&self, index: usize) {
+        let anchor = self
+            .shown_headings
+            .borrow()
+            .get(index)
+            .map(|heading| heading.anchor.clone())
+            .unwrap_or_default();
+        self.webview.evaluate_javascript(
+            &format!(
+                "window.dogmv !== undefined && window.dogmv.scrollToHeading({}, {})",
+                js_string_literal(&anchor),
+                index
+            ),
+            None,
+            None,
+            gio::Cancellable::NONE,
+            |_| {},
+        );
+    }
+
+    /// Scrolls the page currently shown to `target`.
+    pub fn scroll_to(&self, target: &ScrollTarget) {
+        self.webview.evaluate_javascript(
+            &target.script(),
+            None,
+            None,
+            gio::Cancellable::NONE,
+            |_| {},
+        );
+    }
+
+    /// Returns the source of the document shown for `file_path`, if any.
+    fn source_of(&self, file_path: &Path) -> Option<String> {
+        let shown = self.shown_source.lock().ok()?;
+        match shown.as_ref() {
+            Some((path, source)) if path == file_path => Some(source.clone()),
+            _ => None,
+        }
+    }
+
+    /// Renders `file_path` on a worker thread and passes the result to `apply`.
+    ///
+    /// Any render still in progress is cancelled. `apply` runs on the main
+    /// thread and is not called at all if this render gets cancelled in turn.
+    /// Cached documents are applied right away without starting a worker.
+    /// If `source` is given it is rendered instead of reading the file.
+    fn render<F>(&self, file_path: &Path, source: Option<String>, apply: F)
+    where
+        F: FnOnce(&Preview, Result<String>) + 'static,
+    {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        if let Ok(mut active) = self.active_render.lock() {
+            if let Some(previous) = active.replace(Arc::clone(&cancelled)) {
+                previous.store(true, Ordering::Relaxed);
+            }
+        }
+
+        // The cache only knows the file itself, not what it is compared with.
+        // The theme is part of the key, so a render finishing after a theme
+        // switch can't be served for the new theme.
+        let theme = self.theme();
+        let diff_base = self.diff_base();
+        let key = match diff_base {
+            Some(_) => None,
+            None => DocumentKey::for_file(file_path, &theme.name).ok(),
+        };
+        let cached = key
+            .as_ref()
+            .and_then(|key| self.cache.lock().ok().and_then(|mut cache| cache.get(key)));
+        if let Some(document) = cached {
+            info!("Using cached render of {}", file_path.display());
+            self.finish_render();
+            let html = self.set_shown(file_path, document);
+            apply(self, Ok(html));
+            return;
+        }
+
+        // Only show the spinner if rendering takes a noticeable time
+        let spinner = self.spinner.clone();
+        let spinner_token = Arc::clone(&cancelled);
+        let active_render = Arc::clone(&self.active_render);
+        glib::timeout_add_local_once(SPINNER_DELAY, move || {
+            if is_active(&active_render, &spinner_token) {
+                spinner.set_visible(true);
+                spinner.set_spinning(true);
+            }
+        });
+
+        let path = file_path.to_path_buf();
+        let worker_token = Arc::clone(&cancelled);
+        let cache = Arc::clone(&self.cache);
+        let handle = gio::spawn_blocking(move || {
+            if worker_token.load(Ordering::Relaxed) {
+                return None;
+            }
+            let content = match source {
+                Some(source) => source,
+                None => match load_markdown(&path) {
+                    Ok(content) => content,
+                    Err(e) => return Some(Err(e)),
+                },
+            };
+            // Skip the expensive part if the user already moved on
+            if worker_token.load(Ordering::Relaxed) {
+                return None;
+            }
+            // Diagram tools still running when the user moves on get killed
+            let rendered = with_cancel_token(&worker_token, || match diff_base {
+                Some(base) => load_markdown(&base)
+                    .map(|old| render_diff_document(&old, &content, &path, &theme)),
+                None => Ok(render_document(&content, &path, &theme)),
+            });
+            let (html, headings) = match rendered {
+                Ok(rendered) => rendered,
+                Err(e) => return Some(Err(e)),
+            };
+            // Don't cache a document whose diagrams were cut short
+            if worker_token.load(Ordering::Relaxed) {
+                return None;
+            }
+            let document = RenderedDocument {
+                html,
+                source: content,
+                headings,
+            };
+            if let (Some(key), Ok(mut cache)) = (key, cache.lock()) {
+                cache.insert(key, document.clone());
+            }
+            Some(Ok(document))
+        });
+
+        let preview = self.clone();
+        let file_path = file_path.to_path_buf();
+        glib::spawn_future_local(async move {
+            let result = match handle.await {
+                Ok(Some(result)) => result,
+                Ok(None) => return,
+                Err(_) => Err(DogmvError::RenderingError(format!(
+                    "Renderer crashed while rendering {}",
+                    file_path.display()
+                ))),
+            };
+
+            if !is_active(&preview.active_render, &cancelled) {
+                info!("Discarding stale render of {}", file_path.display());
+                return;
+            }
+
+            preview.finish_render();
+            let result = match result {
+                Ok(document) => Ok(preview.set_shown(&file_path, document)),
+                Err(e) => {
+                    preview.notify_outline(&[]);
+                    Err(e)
+                }
+            };
+            apply(&preview, result);
+        });
+    }
+
+    /// Records `document` as the one shown for `file_path` and returns its HTML body.
+    fn set_shown(&self, file_path: &Path, document: RenderedDocument) -> String {
+        if let Ok(mut shown) = self.shown_source.lock() {
+            *shown = Some((file_path.to_path_buf(), document.source));
+        }
+        self.notify_outline(&document.headings);
+        document.html
+    }
+
+    fn notify_outline(&self, headings: &[Heading]) {
+        *self.shown_headings.borrow_mut() = headings.to_vec();
+        for listener in self.outline_listeners.borrow().iter() {
+            listener(headings);
+        }
+    }
+
+    /// Marks the active render as finished and hides the loading indicator.
+    fn finish_render(&self) {
+        if let Ok(mut active) = self.active_render.lock() {
+            *active = None;
         }
+        self.spinner.set_spinning(false);
+        self.spinner.set_visible(false);
+    }
+}
+
+impl Default for Preview {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-/// Displays a source code file with syntax highlighting
-fn display_source_file(webview: &WebView, file_path: &Path) {
-    match load_markdown(file_path) {
-        Ok(source_code) => {
-            let highlighted_html = render_source_code(&source_code, file_path);
+/// Returns `true` if `token` belongs to the render currently in progress.
+fn is_active(active_render: &Mutex<Option<Arc<AtomicBool>>>, token: &Arc<AtomicBool>) -> bool {
+    active_render
+        .lock()
+        .map(|active| active.as_ref().is_some_and(|active| Arc::ptr_eq(active, token)))
+        .unwrap_or(false)
+}
+
+/// Serves the assets bundled with dogmv (e.g. the mermaid runtime) to the WebView.
+///
+/// Pages request them as `dogmv://assets/<name>`, so nothing is fetched from
+/// the network. Only the first call registers the scheme.
+pub fn setup_asset_scheme() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        let Some(context) = WebContext::default() else {
+            warn!("No default web context, bundled assets are unavailable");
+            return;
+        };
+
+        let security_manager = context.security_manager();
+        security_manager.register_uri_scheme_as_secure(ASSET_SCHEME);
+        security_manager.register_uri_scheme_as_cors_enabled(ASSET_SCHEME);
+
+        context.register_uri_scheme(ASSET_SCHEME, |request| {
+            let uri = request.uri().map(|uri| uri.to_string()).unwrap_or_default();
+            match asset_for_uri(&uri) {
+                Some((content, content_type)) => {
+                    let bytes = glib::Bytes::from_static(content);
+                    let stream = gio::MemoryInputStream::from_bytes(&bytes);
+                    request.finish(&stream, content.len() as i64, Some(content_type));
+                }
+                None => {
+                    warn!("Requested asset is not available: {}", uri);
+                    let mut error = glib::Error::new(
+                        gio::IOErrorEnum::NotFound,
+                        &format!("Asset not available: {}", uri),
+                    );
+                    request.finish_error(&mut error);
+                }
+            }
+        });
+        info!("Registered {}:// scheme for bundled assets", ASSET_SCHEME);
+    });
+}
+
+/// Displays a file in the preview (Markdown or source code)
+pub fn display_markdown(preview: &Preview, file_path: &Path) {
+    let path = file_path.to_path_buf();
+    preview.render(file_path, None, move |preview, result| {
+        show_rendered(preview, &path, result);
+    });
+}
+
+/// Displays the file shown in the preview again, e.g. after the theme changed.
+///
+/// The source already in memory is rendered again, so the file is only read
+/// from disk if it is not the document currently shown.
+pub fn rerender_markdown(preview: &Preview, file_path: &Path) {
+    let path = file_path.to_path_buf();
+    let source = preview.source_of(file_path);
+    preview.render(file_path, source, move |preview, result| {
+        show_rendered(preview, &path, result);
+    });
+}
 
-            // Get base directory for relative paths
+/// Loads a rendered file, or an error page, into the WebView
+fn show_rendered(preview: &Preview, file_path: &Path, result: Result<String>) {
+    let webview = &preview.webview;
+    match result {
+        Ok(html_body) => {
+            // Get base directory for relative paths; links are resolved against
+            // it, so it has to be absolute
             let base_dir = file_path
                 .parent()
-                .and_then(|p| p.to_str())
-                .unwrap_or("");
+                .map(|dir| std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf()))
+                .unwrap_or_default();
 
-            let full_html = create_html(&highlighted_html, base_dir);
+            let full_html =
+                create_html(&html_body, &base_dir.to_string_lossy(), &preview.theme());
             webview.load_html(&full_html, None);
-            info!("Source code displayed successfully");
+            info!("File displayed successfully: {}", file_path.display());
         }
         Err(e) => {
-            error!("Failed to load source file '{}': {}", file_path.display(), e);
-            let error_html = create_error_html(
+            error!("Failed to load file '{}': {}", file_path.display(), e);
+            display_error_message(
+                &webview,
                 "Failed to Load File",
                 &format!("Could not read file: {}\n\nError: {}", file_path.display(), e)
             );
-            webview.load_html(&error_html, None);
         }
     }
 }
 
+/// Updates the already loaded page for `file_path` in place.
+///
+/// Only the top-level blocks that differ from the previous render are
+/// replaced. If the page cannot be patched (it is not a dogmv document, or the
+/// file can no longer be read) the file is displayed with a full load instead.
+/// `done` receives `true` if the page was patched.
+pub fn patch_markdown<F>(preview: &Preview, file_path: &Path, done: F)
+where
+    F: FnOnce(bool) + 'static,
+{
+    let path = file_path.to_path_buf();
+    preview.render(file_path, None, move |preview, result| {
+        let html_body = match result {
+            Ok(html_body) => html_body,
+            Err(e) => {
+                show_rendered(preview, &path, Err(e));
+                done(false);
+                return;
+            }
+        };
+
+        let script = format!(
+            "window.dogmv !== undefined && window.dogmv.patch({})",
+            js_string_literal(&html_body)
+        );
+
+        let fallback = preview.clone();
+        preview.webview.evaluate_javascript(
+            &script,
+            None,
+            None,
+            gio::Cancellable::NONE,
+            move |result| {
+                let patched = matches!(result, Ok(ref value) if value.to_boolean());
+                if patched {
+                    info!("Patched document in place: {}", path.display());
+                } else {
+                    info!("Document could not be patched, reloading: {}", path.display());
+                    show_rendered(&fallback, &path, Ok(html_body));
+                }
+                done(patched);
+            },
+        );
+    });
+}
+
+/// Encodes `s` as a JavaScript string literal (including the quotes).
+fn js_string_literal(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{2028}' => out.push_str("\\u2028"),
+            '\u{2029}' => out.push_str("\\u2029"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Returns the JavaScript applying a scroll `position` posted by another page.
+fn sync_scroll_script(position: &str, by_anchor: bool) -> String {
+    format!(
+        "window.dogmv !== undefined && window.dogmv.syncScroll !== undefined && window.dogmv.syncScroll(JSON.parse({}), {})",
+        js_string_literal(position),
+        by_anchor
+    )
+}
+
+/// Reads the vertical scroll offset of the current page and passes it to `callback`.
+///
+/// The callback receives `None` if the offset could not be read, e.g. while no
+/// document is loaded yet.
+pub fn query_scroll_position<F>(webview: &WebView, callback: F)
+where
+    F: FnOnce(Option<f64>) + 'static,
+{
+    webview.evaluate_javascript(
+        "window.scrollY",
+        None,
+        None,
+        gio::Cancellable::NONE,
+        move |result| match result {
+            Ok(value) => callback(Some(value.to_double())),
+            Err(e) => {
+                warn!("Failed to read scroll position: {}", e);
+                callback(None);
+            }
+        },
+    );
+}
+
+/// Scrolls to a pending target whenever a page has finished loading.
+///
+/// The target is taken from `pending`, so it is applied to the next load only.
+pub fn setup_scroll_restore(webview: &WebView, pending: Arc<Mutex<Option<ScrollTarget>>>) {
+    webview.connect_load_changed(move |webview, event| {
+        if event != LoadEvent::Finished {
+            return;
+        }
+
+        let target = pending.lock().ok().and_then(|mut pending| pending.take());
+        if let Some(target) = target {
+            info!("Restoring scroll position: {:?}", target);
+            webview.evaluate_javascript(
+                &target.script(),
+                None,
+                None,
+                gio::Cancellable::NONE,
+                |_| {},
+            );
+        }
+    });
+}
+
 /// Displays the welcome message when no file is selected
 pub fn display_welcome_message(webview: &WebView) {
     info!("Displaying welcome message");
@@ -136,7 +778,14 @@ pub fn display_welcome_message(webview: &WebView) {
     webview.load_html(html, None);
 }
 
+/// Displays an error page in the WebView
+pub fn display_error_message(webview: &WebView, title: &str, message: &str) {
+    webview.load_html(&create_error_html(title, message), None);
+}
+
 /// Creates an error HTML page with styled error message
+///
+/// `title` and `message` are plain text; they are escaped here.
 pub fn create_error_html(title: &str, message: &str) -> String {
     format!(
         r#"<!DOCTYPE html>
@@ -177,6 +826,53 @@ pub fn create_error_html(title: &str, message: &str) -> String {
     <p class="hint">Try using Ctrl+O to open a different file, or check that the file exists and is readable.</p>
 </body>
 </html>"#,
-        title, message
+        escape_html(title),
+        escape_html(message)
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_js_string_literal() {
+        assert_eq!(js_string_literal("plain"), "\"plain\"");
+        assert_eq!(
+            js_string_literal("<p class=\"a\">x\\y</p>\n"),
+            "\"<p class=\\\"a\\\">x\\\\y</p>\\n\""
+        );
+        assert_eq!(js_string_literal("\u{2028}\u{1}"), "\"\\u2028\\u0001\"");
+    }
+
+    #[test]
+    fn test_scroll_target_script() {
+        assert_eq!(ScrollTarget::Offset(120.5).script(), "window.scrollTo(0, 120.5);");
+        let script = ScrollTarget::Fragment("setup-\"cargo\"".to_string()).script();
+        assert!(script.ends_with("})(\"setup-\\\"cargo\\\"\");"));
+    }
+
+    #[test]
+    fn test_sync_scroll_script() {
+        let script = sync_scroll_script("{\"ratio\":0.5,\"anchor\":\"a\\\"b\"}", true);
+        assert!(script.ends_with(
+            "syncScroll(JSON.parse(\"{\\\"ratio\\\":0.5,\\\"anchor\\\":\\\"a\\\\\\\"b\\\"}\"), true)"
+        ));
+        assert!(sync_scroll_script("{}", false).ends_with("(JSON.parse(\"{}\"), false)"));
+    }
+
+    #[test]
+    fn test_reload_mode_from_name() {
+        assert_eq!(ReloadMode::from_name("patch"), Some(ReloadMode::Patch));
+        assert_eq!(ReloadMode::from_name(" Full "), Some(ReloadMode::Full));
+        assert_eq!(ReloadMode::from_name("diff"), None);
+    }
+
+    #[test]
+    fn test_error_html_is_escaped() {
+        let html = create_error_html("<b>", "bad.toml: <img src=x onerror=alert(1)>");
+        assert!(html.contains("<h1>&lt;b&gt;</h1>"));
+        assert!(html.contains("bad.toml: &lt;img src=x onerror=alert(1)&gt;"));
+        assert!(!html.contains("<img"));
+    }
+}
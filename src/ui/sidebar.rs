@@ -1,5 +1,5 @@
 use gtk4::prelude::*;
-use gtk4::{gdk, Button, Paned, ScrolledWindow};
+use gtk4::{gdk, Button, Paned, Widget};
 use std::sync::{Arc, Mutex};
 
 /// Setup CSS for toggle button to remove borders completely
@@ -36,10 +36,10 @@ pub fn setup_toggle_button_css() {
 /// Setup toggle button for sidebar visibility
 pub fn setup_toggle_button(
     toggle_button: &Button,
-    tree_scroll: &ScrolledWindow,
+    sidebar_content: &impl IsA<Widget>,
     paned: &Paned,
 ) {
-    let tree_scroll = tree_scroll.clone();
+    let sidebar_content = sidebar_content.clone().upcast::<Widget>();
     let toggle_button = toggle_button.clone();
     let paned = paned.clone();
 
@@ -47,7 +47,7 @@ pub fn setup_toggle_button(
     let original_width = Arc::new(Mutex::new(250)); // Default initial width
 
     toggle_button.connect_clicked(move |btn| {
-        let is_visible = tree_scroll.is_visible();
+        let is_visible = sidebar_content.is_visible();
 
         if is_visible {
             // Closing sidebar - store current width and minimize
@@ -58,7 +58,7 @@ pub fn setup_toggle_button(
 
             // Set to minimum width (just enough for toggle button, approximately 40-50px)
             paned.set_position(40);
-            tree_scroll.set_visible(false);
+            sidebar_content.set_visible(false);
 
             btn.set_icon_name("pan-end-symbolic");
             btn.set_tooltip_text(Some("サイドバー展開"));
@@ -67,7 +67,7 @@ pub fn setup_toggle_button(
             if let Ok(width) = original_width.lock() {
                 paned.set_position(*width);
             }
-            tree_scroll.set_visible(true);
+            sidebar_content.set_visible(true);
 
             btn.set_icon_name("pan-start-symbolic");
             btn.set_tooltip_text(Some("サイドバー閉じる"));
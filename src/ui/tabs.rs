@@ -0,0 +1,547 @@
+use crate::app_state::AppState;
+use crate::file_system::WatchEvent;
+use crate::markdown::{Theme, ThemeChoice};
+use crate::ui::{display_welcome_message, prefers_dark, Preview, ReloadMode};
+use gtk4::prelude::*;
+use gtk4::{glib, Box as GtkBox, Button, EventControllerFocus, Label, Notebook, Orientation, Paned, Widget};
+use log::info;
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Delay before the first attempt to re-arm a lost directory watch
+const REARM_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Longest delay between attempts to re-arm a lost directory watch
+const REARM_RETRY_MAX: Duration = Duration::from_secs(30);
+
+/// Tab label while no file is shown
+const EMPTY_TAB_TITLE: &str = "新しいタブ";
+
+/// How the panes of the split view scroll together
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncScroll {
+    /// Each pane scrolls on its own
+    #[default]
+    Off,
+    /// The panes keep the same relative position
+    Position,
+    /// The panes are aligned on headings with the same anchor, if any
+    Headings,
+}
+
+/// The preview tabs, and the pane shown next to them while the view is split.
+///
+/// Every tab (and the split pane) has a `Preview` and an `AppState` of its
+/// own, so the current file, the watcher, the scroll positions and the
+/// history are per tab. The one shown in the notebook or the split pane,
+/// whichever was focused last, is the current one. The tab strip is only
+/// shown while more than one tab is open.
+#[derive(Clone)]
+pub struct Tabs {
+    paned: Paned,
+    notebook: Notebook,
+    tabs: Rc<RefCell<Vec<AppState>>>,
+    // State of the second pane while the view is split
+    split: Rc<RefCell<Option<AppState>>>,
+    // Whether the split pane is the current one
+    split_active: Rc<Cell<bool>>,
+    sync_scroll: Rc<Cell<SyncScroll>>,
+    root_dir: PathBuf,
+    reload_mode: ReloadMode,
+    reload_debounce: Duration,
+    // Called with the state of every tab (or split pane) opened
+    tab_added_listeners: Rc<RefCell<Vec<Box<dyn Fn(&AppState)>>>>,
+    // Called with the state of the tab switched to
+    switched_listeners: Rc<RefCell<Vec<Box<dyn Fn(&AppState)>>>>,
+    // Called with the orientation of the split, or `None` when it is closed
+    split_listeners: Rc<RefCell<Vec<Box<dyn Fn(Option<Orientation>)>>>>,
+}
+
+impl Tabs {
+    /// Creates the (still empty) tab view; see `open_tab`.
+    pub fn new(root_dir: &Path, reload_mode: ReloadMode, reload_debounce: Duration) -> Self {
+        let notebook = Notebook::new();
+        notebook.set_scrollable(true);
+        notebook.set_show_border(false);
+        notebook.set_show_tabs(false);
+        notebook.set_hexpand(true);
+        notebook.set_vexpand(true);
+
+        let paned = Paned::new(Orientation::Horizontal);
+        paned.set_start_child(Some(&notebook));
+        paned.set_shrink_start_child(false);
+        paned.set_shrink_end_child(false);
+
+        let tabs = Self {
+            paned,
+            notebook,
+            tabs: Rc::new(RefCell::new(Vec::new())),
+            split: Rc::new(RefCell::new(None)),
+            split_active: Rc::new(Cell::new(false)),
+            sync_scroll: Rc::new(Cell::new(SyncScroll::Off)),
+            root_dir: root_dir.to_path_buf(),
+            reload_mode,
+            reload_debounce,
+            tab_added_listeners: Rc::new(RefCell::new(Vec::new())),
+            switched_listeners: Rc::new(RefCell::new(Vec::new())),
+            split_listeners: Rc::new(RefCell::new(Vec::new())),
+        };
+
+        // Showing a tab makes the notebook the current pane
+        let tabs_clone = tabs.clone();
+        tabs.notebook.connect_page_notify(move |_| {
+            tabs_clone.split_active.set(false);
+            tabs_clone.notify_switched();
+        });
+        tabs
+    }
+
+    /// Returns the widget to pack into the window.
+    pub fn widget(&self) -> &Paned {
+        &self.paned
+    }
+
+    /// Calls `f` with the state of each tab (or split pane) opened from now
+    /// on, before it shows anything.
+    pub fn connect_tab_added<F: Fn(&AppState) + 'static>(&self, f: F) {
+        self.tab_added_listeners.borrow_mut().push(Box::new(f));
+    }
+
+    /// Calls `f` with the state of the tab shown whenever another tab is shown.
+    pub fn connect_switched<F: Fn(&AppState) + 'static>(&self, f: F) {
+        self.switched_listeners.borrow_mut().push(Box::new(f));
+    }
+
+    /// Calls `f` whenever the view is split (with its orientation) or unsplit.
+    pub fn connect_split_changed<F: Fn(Option<Orientation>) + 'static>(&self, f: F) {
+        self.split_listeners.borrow_mut().push(Box::new(f));
+    }
+
+    /// Returns the state of the current tab: the split pane if it was focused
+    /// last, otherwise the tab shown in the notebook.
+    pub fn current(&self) -> Option<AppState> {
+        if self.split_active.get() {
+            if let Some(split) = self.split.borrow().clone() {
+                return Some(split);
+            }
+        }
+        self.shown_tab()
+    }
+
+    /// Returns the state of the tab shown in the notebook.
+    fn shown_tab(&self) -> Option<AppState> {
+        let page = self.notebook.nth_page(self.notebook.current_page())?;
+        self.tab_for(&page)
+    }
+
+    /// Returns `true` if `state` belongs to the current tab.
+    pub fn is_current(&self, state: &AppState) -> bool {
+        self.current().is_some_and(|current| current.same_tab(state))
+    }
+
+    /// Returns the states of all tabs, including the split pane.
+    pub fn all(&self) -> Vec<AppState> {
+        let mut all = self.tabs.borrow().clone();
+        all.extend(self.split.borrow().iter().cloned());
+        all
+    }
+
+    /// Opens a tab next to the current one and shows it.
+    ///
+    /// The tab displays `file` if given, or the welcome screen.
+    pub fn open_tab(&self, file: Option<&Path>) -> AppState {
+        let state = self.create_state();
+        self.tabs.borrow_mut().push(state.clone());
+
+        let label = self.create_tab_label(&state);
+        let position = self.notebook.current_page().map(|page| page + 1);
+        let page = self
+            .notebook
+            .insert_page(state.preview.widget(), Some(&label), position);
+        self.notebook.set_show_tabs(self.notebook.n_pages() > 1);
+        self.notebook.set_current_page(Some(page));
+
+        show_initial(&state, file);
+        state
+    }
+
+    /// Splits the view in two panes side by side (`Orientation::Horizontal`)
+    /// or on top of each other, or closes the second pane (`None`).
+    ///
+    /// The second pane starts with the file of the current tab and becomes
+    /// the current one.
+    pub fn set_split(&self, orientation: Option<Orientation>) {
+        let Some(orientation) = orientation else {
+            let Some(state) = self.split.borrow_mut().take() else {
+                return;
+            };
+            info!("Closing split view");
+            state.close();
+            self.paned.set_end_child(None::<&Widget>);
+            if self.split_active.replace(false) {
+                self.notify_switched();
+            }
+            self.notify_split(None);
+            return;
+        };
+
+        self.paned.set_orientation(orientation);
+        if self.split.borrow().is_none() {
+            info!("Splitting view ({:?})", orientation);
+            let file = self
+                .current()
+                .and_then(|state| state.current_file.lock().ok().and_then(|f| f.clone()));
+            let state = self.create_state();
+            *self.split.borrow_mut() = Some(state.clone());
+            self.paned.set_end_child(Some(state.preview.widget()));
+            self.split_active.set(true);
+            self.notify_switched();
+            show_initial(&state, file.as_deref());
+        }
+
+        // Share the space evenly
+        let size = match orientation {
+            Orientation::Vertical => self.paned.height(),
+            _ => self.paned.width(),
+        };
+        if size > 0 {
+            self.paned.set_position(size / 2);
+        }
+        self.notify_split(Some(orientation));
+    }
+
+    /// Returns the orientation of the split, or `None` if the view is not split.
+    pub fn split_orientation(&self) -> Option<Orientation> {
+        self.split
+            .borrow()
+            .as_ref()
+            .map(|_| self.paned.orientation())
+    }
+
+    /// Sets how the panes of the split view scroll together.
+    pub fn set_sync_scroll(&self, mode: SyncScroll) {
+        info!("Scroll sync: {:?}", mode);
+        self.sync_scroll.set(mode);
+    }
+
+    /// Closes the current tab (or the split pane).
+    pub fn close_current(&self) {
+        if let Some(state) = self.current() {
+            self.close_tab(&state);
+        }
+    }
+
+    /// Closes the tab of `state`; closing the last tab leaves an empty one.
+    ///
+    /// Closing the split pane unsplits the view.
+    pub fn close_tab(&self, state: &AppState) {
+        if self.is_split_pane(state) {
+            self.set_split(None);
+            return;
+        }
+        if self.notebook.n_pages() == 1 {
+            self.open_tab(None);
+        }
+        let Some(page) = self.notebook.page_num(state.preview.widget()) else {
+            return;
+        };
+        info!("Closing tab {}", page);
+
+        state.close();
+        self.tabs.borrow_mut().retain(|tab| !tab.same_tab(state));
+        self.notebook.remove_page(Some(page));
+        self.notebook.set_show_tabs(self.notebook.n_pages() > 1);
+    }
+
+    /// Shows the next tab, or the previous one if `backwards`, wrapping around.
+    pub fn cycle(&self, backwards: bool) {
+        if let Some(current) = self.notebook.current_page() {
+            let next = cycled_page(current, self.notebook.n_pages(), backwards);
+            self.notebook.set_current_page(Some(next));
+        }
+    }
+
+    /// Loads a theme picked by the user and applies it to every tab.
+    pub fn choose_theme(&self, choice: &ThemeChoice) {
+        let Some(current) = self.current() else {
+            return;
+        };
+        current.choose_theme(choice);
+        let theme = current.preview.theme();
+        for state in self.all() {
+            state.apply_theme(theme.clone());
+        }
+    }
+
+    /// Follows a change of the desktop color scheme in every tab.
+    pub fn color_scheme_changed(&self, prefer_dark: bool) {
+        for state in self.all() {
+            state.color_scheme_changed(prefer_dark);
+        }
+    }
+
+    /// Creates the state of a tab (or split pane) and announces it.
+    fn create_state(&self) -> AppState {
+        let current = self.current();
+        let theme = current
+            .as_ref()
+            .map(|current| current.preview.theme())
+            .unwrap_or_else(|| Theme::for_color_scheme(prefers_dark()));
+        let preview = Preview::new();
+        preview.set_theme(theme);
+
+        let (watch_tx, watch_rx) = async_channel::unbounded();
+        let state = match current {
+            Some(ref current) => current.new_tab(&preview, watch_tx),
+            None => AppState::new(&preview, &self.root_dir, watch_tx, self.reload_mode),
+        };
+        setup_file_watcher(&state, watch_rx, self.reload_debounce);
+
+        // The pane focused last is the current one. The WebView owns these
+        // handlers, so they hold the state weakly to let closed tabs go.
+        let focus = EventControllerFocus::new();
+        let tabs = self.clone();
+        let focused = state.downgrade();
+        focus.connect_enter(move |_| {
+            let Some(focused) = focused.upgrade() else {
+                return;
+            };
+            let split_active = tabs.is_split_pane(&focused);
+            if tabs.split_active.replace(split_active) != split_active {
+                tabs.notify_switched();
+            }
+        });
+        preview.webview.add_controller(focus);
+
+        let tabs = self.clone();
+        let scrolled = state.downgrade();
+        preview.connect_scrolled(move |position| {
+            if let Some(scrolled) = scrolled.upgrade() {
+                tabs.sync_panes(&scrolled, position);
+            }
+        });
+
+        for listener in self.tab_added_listeners.borrow().iter() {
+            listener(&state);
+        }
+        state
+    }
+
+    /// Scrolls the other pane of the split view after `state` was scrolled to `position`.
+    fn sync_panes(&self, state: &AppState, position: &str) {
+        let by_anchor = match self.sync_scroll.get() {
+            SyncScroll::Off => return,
+            SyncScroll::Position => false,
+            SyncScroll::Headings => true,
+        };
+        let Some(split) = self.split.borrow().clone() else {
+            return;
+        };
+        let Some(shown) = self.shown_tab() else {
+            return;
+        };
+        // Tabs in the background are not synced
+        let other = if split.same_tab(state) {
+            shown
+        } else if shown.same_tab(state) {
+            split
+        } else {
+            return;
+        };
+        other.preview.sync_scroll(position, by_anchor);
+    }
+
+    fn is_split_pane(&self, state: &AppState) -> bool {
+        self.split
+            .borrow()
+            .as_ref()
+            .is_some_and(|split| split.same_tab(state))
+    }
+
+    fn notify_switched(&self) {
+        if let Some(state) = self.current() {
+            for listener in self.switched_listeners.borrow().iter() {
+                listener(&state);
+            }
+        }
+    }
+
+    fn notify_split(&self, orientation: Option<Orientation>) {
+        for listener in self.split_listeners.borrow().iter() {
+            listener(orientation);
+        }
+    }
+
+    fn tab_for(&self, page: &Widget) -> Option<AppState> {
+        self.tabs
+            .borrow()
+            .iter()
+            .find(|state| state.preview.widget().upcast_ref::<Widget>() == page)
+            .cloned()
+    }
+
+    /// Creates the tab label: the file name and a close button.
+    fn create_tab_label(&self, state: &AppState) -> GtkBox {
+        let title = Label::new(Some(EMPTY_TAB_TITLE));
+        title.set_ellipsize(gtk4::pango::EllipsizeMode::End);
+        title.set_max_width_chars(24);
+
+        let title_weak = title.downgrade();
+        state.connect_file_opened(move |path| {
+            if let Some(title) = title_weak.upgrade() {
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| path.display().to_string());
+                title.set_text(&name);
+                title.set_tooltip_text(Some(&path.display().to_string()));
+            }
+        });
+
+        let close_button = Button::from_icon_name("window-close-symbolic");
+        close_button.set_tooltip_text(Some("タブを閉じる (Ctrl+W)"));
+        close_button.add_css_class("flat");
+        let tabs = self.clone();
+        let state = state.clone();
+        close_button.connect_clicked(move |_| tabs.close_tab(&state));
+
+        let label = GtkBox::new(Orientation::Horizontal, 4);
+        label.append(&title);
+        label.append(&close_button);
+        label
+    }
+}
+
+/// Shows `file` in a new tab, or the welcome screen.
+fn show_initial(state: &AppState, file: Option<&Path>) {
+    match file {
+        Some(file) => state.open_file(file),
+        None => display_welcome_message(&state.preview.webview),
+    }
+}
+
+/// Returns the page after `current` of `pages`, or the one before it if `backwards`.
+fn cycled_page(current: u32, pages: u32, backwards: bool) -> u32 {
+    if pages == 0 {
+        return current;
+    }
+    if backwards {
+        (current + pages - 1) % pages
+    } else {
+        (current + 1) % pages
+    }
+}
+
+/// Reloads the tab's file whenever its watcher reports a change.
+///
+/// A lost directory watch is re-armed on a timer that backs off while the
+/// directory stays missing; events keep being served in the meantime.
+fn setup_file_watcher(
+    state: &AppState,
+    events: async_channel::Receiver<WatchEvent>,
+    debounce: Duration,
+) {
+    info!("Setting up file watcher (debounce: {:?})", debounce);
+
+    // A single future on the main loop serves every file the watcher is moved to.
+    // It sleeps until the watcher thread sends an event, so nothing is polled,
+    // and ends when the tab is closed.
+    let state = state.clone();
+
+    glib::spawn_future_local(async move {
+        // Delay before the next attempt to re-arm a lost watch, while one is due
+        let mut rearm_retry: Option<Duration> = None;
+
+        loop {
+            // Keep serving events while waiting for a lost directory to come back
+            let first = match rearm_retry {
+                Some(delay) => match glib::future_with_timeout(delay, events.recv()).await {
+                    Ok(Ok(event)) => Some(event),
+                    Ok(Err(_)) => break,
+                    Err(_) => None,
+                },
+                None => match events.recv().await {
+                    Ok(event) => Some(event),
+                    Err(_) => break,
+                },
+            };
+
+            // Editors often emit several events per save; let the burst settle
+            if first.is_some() && !debounce.is_zero() {
+                glib::timeout_future(debounce).await;
+            }
+
+            let mut pending: Vec<WatchEvent> = first.into_iter().collect();
+            while let Ok(event) = events.try_recv() {
+                pending.push(event);
+            }
+
+            // Without an event the retry timer expired. While a retry is due,
+            // the timer alone decides when to try again.
+            let mut changed = None;
+            let mut rearm = pending.is_empty();
+            for event in pending {
+                match event {
+                    WatchEvent::Changed(path) => changed = Some(path),
+                    WatchEvent::Rearm => rearm |= rearm_retry.is_none(),
+                }
+            }
+
+            if rearm {
+                let rearmed = state.watcher.lock().map(|mut w| w.rearm()).unwrap_or(true);
+                rearm_retry = if rearmed {
+                    None
+                } else {
+                    Some(next_rearm_delay(rearm_retry))
+                };
+            }
+
+            if let Some(path) = changed {
+                // Ignore events that were queued before the watcher moved on
+                let is_current = state
+                    .watcher
+                    .lock()
+                    .map(|watcher| watcher.is_watching(&path))
+                    .unwrap_or(false);
+
+                if is_current {
+                    state.reload();
+                }
+            }
+        }
+    });
+}
+
+/// Returns the delay before the next attempt to re-arm a watch, backing off
+/// from `REARM_RETRY_INTERVAL` to `REARM_RETRY_MAX`.
+fn next_rearm_delay(previous: Option<Duration>) -> Duration {
+    previous.map_or(REARM_RETRY_INTERVAL, |delay| (delay * 2).min(REARM_RETRY_MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycled_page_wraps_around() {
+        assert_eq!(cycled_page(0, 3, false), 1);
+        assert_eq!(cycled_page(2, 3, false), 0);
+        assert_eq!(cycled_page(0, 3, true), 2);
+        assert_eq!(cycled_page(1, 3, true), 0);
+        assert_eq!(cycled_page(0, 1, false), 0);
+    }
+
+    #[test]
+    fn test_rearm_retries_back_off() {
+        let mut delay = next_rearm_delay(None);
+        assert_eq!(delay, REARM_RETRY_INTERVAL);
+        delay = next_rearm_delay(Some(delay));
+        assert_eq!(delay, REARM_RETRY_INTERVAL * 2);
+        for _ in 0..20 {
+            delay = next_rearm_delay(Some(delay));
+        }
+        assert_eq!(delay, REARM_RETRY_MAX);
+    }
+}
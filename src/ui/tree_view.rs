@@ -1,15 +1,20 @@
+use crate::app_state::same_file;
 use crate::models::FileItem;
-use crate::ui::preview::display_markdown;
+use crate::ui::Tabs;
 use gtk4::prelude::*;
-use gtk4::{gio, Box as GtkBox, Label, ListView, Orientation, ScrolledWindow, SignalListItemFactory, SingleSelection, TreeListModel, TreeListRow};
+use gtk4::{gdk, gio, Box as GtkBox, EventSequenceState, GestureClick, Label, ListView, Orientation, ScrolledWindow, SignalListItemFactory, SingleSelection, TreeListModel, TreeListRow};
 use log::{info, warn};
 use std::fs;
-use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
-use webkit6::WebView;
-
-/// Creates a tree view for browsing directory structure
-pub fn create_tree_view(root_dir: &Path) -> (ScrolledWindow, SingleSelection) {
+use std::path::Path;
+use std::rc::Rc;
+
+/// Creates a tree view for browsing directory structure.
+///
+/// Middle-clicking a file calls `open_in_new_tab` with its path.
+pub fn create_tree_view(
+    root_dir: &Path,
+    open_in_new_tab: Rc<dyn Fn(&Path)>,
+) -> (ScrolledWindow, SingleSelection) {
     let scroll = ScrolledWindow::new();
     scroll.set_vexpand(true);
     scroll.set_hexpand(true);
@@ -71,6 +76,46 @@ pub fn create_tree_view(root_dir: &Path) -> (ScrolledWindow, SingleSelection) {
         row.append(&icon);
         row.append(&label);
 
+        // Titles are read in the background and may arrive after the row was bound
+        let list_item_weak = list_item.downgrade();
+        let label_weak = label.downgrade();
+        list_item
+            .property_expression("item")
+            .chain_property::<TreeListRow>("item")
+            .chain_property::<FileItem>("title")
+            .watch(Some(list_item), move || {
+                let file_item = list_item_weak
+                    .upgrade()
+                    .and_then(|list_item| list_item.item())
+                    .and_downcast::<TreeListRow>()
+                    .and_then(|row| row.item().and_downcast::<FileItem>());
+                if let (Some(file_item), Some(label)) = (file_item, label_weak.upgrade()) {
+                    set_name_label(&label, &file_item);
+                }
+            });
+
+        // Middle click: open the file in a new tab
+        let gesture = GestureClick::new();
+        gesture.set_button(gdk::BUTTON_MIDDLE);
+        let list_item_weak = list_item.downgrade();
+        let open_in_new_tab = Rc::clone(&open_in_new_tab);
+        gesture.connect_pressed(move |gesture, _, _, _| {
+            let file_item = list_item_weak
+                .upgrade()
+                .and_then(|list_item| list_item.item())
+                .and_downcast::<TreeListRow>()
+                .and_then(|row| row.item().and_downcast::<FileItem>());
+            if let Some(file_item) = file_item {
+                if !file_item.is_dir() && !file_item.is_symlink() {
+                    gesture.set_state(EventSequenceState::Claimed);
+                    let path = file_item.path_buf();
+                    info!("Opening in a new tab: {}", path.display());
+                    open_in_new_tab(&path);
+                }
+            }
+        });
+        row.add_controller(gesture);
+
         list_item.set_child(Some(&row));
     });
 
@@ -107,15 +152,7 @@ pub fn create_tree_view(root_dir: &Path) -> (ScrolledWindow, SingleSelection) {
         };
         icon_label.set_text(icon_text);
 
-        // Set file name with tooltip
-        let name = file_item.name();
-        name_label.set_text(&name);
-        name_label.set_tooltip_text(Some(&name));
-
-        // Add (symlink) suffix for symlinks
-        if file_item.is_symlink() {
-            name_label.set_text(&format!("{} (symlink)", name));
-        }
+        set_name_label(&name_label, &file_item);
     });
 
     list_view.set_factory(Some(&factory));
@@ -124,6 +161,24 @@ pub fn create_tree_view(root_dir: &Path) -> (ScrolledWindow, SingleSelection) {
     (scroll, selection_model)
 }
 
+/// Shows the file name (or front matter title) of `file_item` in `label`, with a tooltip.
+fn set_name_label(label: &Label, file_item: &FileItem) {
+    let name = file_item.name();
+    let title = file_item.title();
+    if title.is_empty() {
+        label.set_text(&name);
+        label.set_tooltip_text(Some(&name));
+    } else {
+        label.set_text(&title);
+        label.set_tooltip_text(Some(&format!("{}\n{}", title, name)));
+    }
+
+    // Add (symlink) suffix for symlinks
+    if file_item.is_symlink() {
+        label.set_text(&format!("{} (symlink)", name));
+    }
+}
+
 /// Load directory items and sort them (directories first, then alphabetically)
 pub fn load_directory_items(dir_path: &Path) -> Vec<FileItem> {
     let mut items = Vec::new();
@@ -160,17 +215,20 @@ pub fn load_directory_items(dir_path: &Path) -> Vec<FileItem> {
         }
     });
 
+    // Front matter titles are read off the main thread
+    for item in &items {
+        item.load_title();
+    }
+
     items
 }
 
-/// Setup file selection handler for tree view
+/// Setup file selection handler for tree view; files open in the current tab
 pub fn setup_file_selection_handler(
     selection_model: &SingleSelection,
-    webview: &WebView,
-    current_file: Arc<Mutex<Option<PathBuf>>>,
+    tabs: &Tabs,
 ) {
-    let webview = webview.clone();
-    let current_file = current_file.clone();
+    let tabs = tabs.clone();
 
     selection_model.connect_selected_item_notify(move |model| {
         if let Some(selected_item) = model.selected_item() {
@@ -178,17 +236,19 @@ pub fn setup_file_selection_handler(
                 if let Some(file_item) = tree_list_row.item().and_downcast::<FileItem>() {
                     let path = file_item.path_buf();
 
-                    // Only open files, not directories or symlinks
+                    // Only open files, not directories or symlinks. The file
+                    // shown is also selected when it was opened some other way.
                     if !file_item.is_dir() && !file_item.is_symlink() {
-                        info!("File selected: {}", path.display());
-
-                        // Update current file in app state
-                        if let Ok(mut current) = current_file.lock() {
-                            *current = Some(path.clone());
+                        let Some(state) = tabs.current() else {
+                            return;
+                        };
+                        if state.is_current_file(&path) {
+                            return;
                         }
+                        info!("File selected: {}", path.display());
 
-                        // Display markdown
-                        display_markdown(&webview, &path);
+                        // Update current file, watcher and preview
+                        state.open_file(&path);
                     } else if file_item.is_dir() && !file_item.is_symlink() {
                         // Toggle directory expansion
                         tree_list_row.set_expanded(!tree_list_row.is_expanded());
@@ -198,3 +258,81 @@ pub fn setup_file_selection_handler(
         }
     });
 }
+
+/// Keeps the tree selection on the file shown in the current tab, however it
+/// was opened.
+pub fn setup_tree_sync(selection_model: &SingleSelection, tabs: &Tabs) {
+    let selection_clone = selection_model.clone();
+    let tabs_clone = tabs.clone();
+    tabs.connect_tab_added(move |state| {
+        let selection_model = selection_clone.clone();
+        let tabs = tabs_clone.clone();
+        let tab = state.downgrade();
+        state.connect_file_opened(move |path| {
+            if tab.upgrade().is_some_and(|tab| tabs.is_current(&tab)) {
+                sync_selection(&selection_model, Some(path));
+            }
+        });
+        let selection_model = selection_clone.clone();
+        state.connect_reloaded(move |path| reload_title(&selection_model, path));
+    });
+
+    let selection_model = selection_model.clone();
+    tabs.connect_switched(move |state| {
+        let current_file = state.current_file.lock().ok().and_then(|f| f.clone());
+        sync_selection(&selection_model, current_file.as_deref());
+    });
+}
+
+/// Reads the front matter title of `path` again, if it is shown in the tree.
+fn reload_title(selection_model: &SingleSelection, path: &Path) {
+    let file_items = (0..selection_model.n_items()).filter_map(|position| {
+        selection_model
+            .item(position)
+            .and_downcast::<TreeListRow>()
+            .and_then(|row| row.item().and_downcast::<FileItem>())
+    });
+    for file_item in file_items {
+        if same_file(&file_item.path_buf(), path) {
+            file_item.load_title();
+        }
+    }
+}
+
+/// Selects `path` in the tree, or nothing if there is no such file in it.
+fn sync_selection(selection_model: &SingleSelection, path: Option<&Path>) {
+    if !path.is_some_and(|path| select_file(selection_model, path)) {
+        selection_model.set_selected(gtk4::INVALID_LIST_POSITION);
+    }
+}
+
+/// Selects `path` in the tree, expanding the directories leading to it.
+///
+/// Returns `false` if the file is not in the tree.
+pub fn select_file(selection_model: &SingleSelection, path: &Path) -> bool {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    // Expanded directories insert their children right after themselves,
+    // so the walk continues into them
+    let mut position = 0;
+    while position < selection_model.n_items() {
+        let row = selection_model.item(position).and_downcast::<TreeListRow>();
+        if let Some((row, file_item)) =
+            row.and_then(|row| row.item().and_downcast::<FileItem>().map(|item| (row, item)))
+        {
+            let item_path = file_item.path_buf();
+            if !file_item.is_dir() && !file_item.is_symlink() && same_file(&item_path, &target) {
+                selection_model.set_selected(position);
+                return true;
+            }
+            if file_item.is_dir() && !file_item.is_symlink() {
+                let dir = fs::canonicalize(&item_path).unwrap_or(item_path);
+                if target.starts_with(&dir) {
+                    row.set_expanded(true);
+                }
+            }
+        }
+        position += 1;
+    }
+    false
+}